@@ -1,53 +1,118 @@
+# Shows the time in 24-hour format, read from the keypad at startup
+#
+# V0-V5 hold the digits (hh:mm:ss), VA-VD are used for drawing
+
+colon = 0x000 # colon sprite lives in the (unused) interpreter area
+
 # loading colon separator sprite
-6000 6180 6200 6380 6400
-A000 F455
+LD V0, 0x00
+LD V1, 0x80
+LD V2, 0x00
+LD V3, 0x80
+LD V4, 0x00
+LD I, colon
+LD [I], V4
 
-# reading input for start time 
-F00A F10A F20A F30A F40A F50A
+# reading input for start time
+LD V0, K
+LD V1, K
+LD V2, K
+LD V3, K
+LD V4, K
+LD V5, K
 
-# drawing
-00E0
-6A01 6B01 6C05 6D02
+draw:
+CLS
+LD VA, 1    # x
+LD VB, 1    # y
+LD VC, 5    # digit width
+LD VD, 2    # colon width
 # hour
-F029 DAB5
-8AC4
-F129 DAB5
-8AC4
+LD F, V0
+DRW VA, VB, 5
+ADD VA, VC
+LD F, V1
+DRW VA, VB, 5
+ADD VA, VC
 # colon
-A000 DAB5
-8AD4
+LD I, colon
+DRW VA, VB, 5
+ADD VA, VD
 # minute
-F229 DAB5
-8AC4
-F329 DAB5
-8AC4
+LD F, V2
+DRW VA, VB, 5
+ADD VA, VC
+LD F, V3
+DRW VA, VB, 5
+ADD VA, VC
 # colon
-A000 DAB5
-8AD4
+LD I, colon
+DRW VA, VB, 5
+ADD VA, VD
 # second
-F429 DAB5
-8AC4
-F529 DAB5
+LD F, V4
+DRW VA, VB, 5
+ADD VA, VC
+LD F, V5
+DRW VA, VB, 5
 
 # wait one second
-6A3C FA15
-FA07
-3A00
-1256
+LD VA, 60
+LD DT, VA
+wait:
+LD VA, DT
+SE VA, 0
+JP wait
 
-# incrementing time
-7501
-8A50 6B0A 8AB5
-3F00 7401 3F00 6500
-8A40 6B06 8AB5
-3F00 7301 3F00 6400
-8A30 6B0A 8AB5
-3F00 7201 3F00 6300
-8A20 6B06 8AB5
-3F00 7101 3F00 6200
-8A10 6B0A 8AB5
-3F00 7001 3F00 6100
-8A00 8A0E 8A0E 8A0E 8A04 8A04 8A14
-6B18 8AB5
-3F00 6000 3F00 6100
-121A
+# incrementing time, carrying into the next digit on overflow
+ADD V5, 1
+LD VA, V5
+LD VB, 10
+SUB VA, VB
+SE VF, 0
+ADD V4, 1
+SE VF, 0
+LD V5, 0
+LD VA, V4
+LD VB, 6
+SUB VA, VB
+SE VF, 0
+ADD V3, 1
+SE VF, 0
+LD V4, 0
+LD VA, V3
+LD VB, 10
+SUB VA, VB
+SE VF, 0
+ADD V2, 1
+SE VF, 0
+LD V3, 0
+LD VA, V2
+LD VB, 6
+SUB VA, VB
+SE VF, 0
+ADD V1, 1
+SE VF, 0
+LD V2, 0
+LD VA, V1
+LD VB, 10
+SUB VA, VB
+SE VF, 0
+ADD V0, 1
+SE VF, 0
+LD V1, 0
+# hours wrap at 24 (VA = V0 * 10 + V1)
+LD VA, V0
+SHL VA, V0
+SHL VA, V0
+SHL VA, V0
+ADD VA, V0
+ADD VA, V0
+ADD VA, V1
+LD VB, 24
+SUB VA, VB
+SE VF, 0
+LD V0, 0
+SE VF, 0
+LD V1, 0
+JP draw
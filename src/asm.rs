@@ -0,0 +1,666 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{ Instruction, MEM_SIZE, PROGRAM_START_ADDR };
+
+/// Assembler error, pointing at the offending source line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// Line number (starting at 1)
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Output of a successful assembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    /// ROM image, to be loaded at the program start address
+    pub rom: Vec<u8>,
+    /// Address of every label
+    pub labels: BTreeMap<String, u16>,
+    /// Source line (starting at 1), address and size of every instruction
+    /// and data statement, in order
+    pub lines: Vec<(usize, u16, usize)>,
+}
+
+/// Assembles CHIP-8 source into a ROM image
+///
+/// The syntax is the same as the disassembler output (whose address and raw
+/// bytes columns are skipped, so a listing assembles back as-is), plus:
+/// - `label:` at the start of a line names the address of what follows
+/// - `name = expr` defines a constant
+/// - `db`/`dw` emit data bytes/words, `org` moves the output address forward
+/// - numbers can be decimal, hex (`0x1F`, `$1F`) or binary (`0b0101`), and
+///   expressions can add and subtract labels, constants and numbers
+/// - `#` and `;` start comments
+///
+/// Lines of whitespace separated, 4 digit hex words (the original
+/// `assembler.py` format) are copied straight into the output.
+///
+/// All errors are collected rather than stopping at the first one.
+pub fn assemble(source: &str) -> Result<Assembly, Vec<AsmError>> {
+    let mut assembler = Assembler::new();
+    for (i, line) in source.lines().enumerate() {
+        if let Err(message) = assembler.parse_line(line) {
+            assembler.errors.push(AsmError { line: i + 1, message });
+        }
+        assembler.line += 1;
+    }
+    assembler.emit()
+}
+
+/// Parsed source statement, waiting on labels to be resolved
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Raw(Vec<u16>),
+}
+
+/// Operand, classified by its syntax
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Reg(u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    /// `LONG expr`, the 16 bit address of `LD I, LONG expr` (XO-CHIP)
+    Long(String),
+    Expr(String),
+}
+
+struct Assembler {
+    /// Line currently being parsed (starting at 0)
+    line: usize,
+    /// Address the next statement will be placed at, which is one past the
+    /// end of memory once a program fills it
+    addr: usize,
+    /// Statements with the line and address they came from
+    statements: Vec<(usize, u16, Statement)>,
+    labels: BTreeMap<String, u16>,
+    constants: BTreeMap<String, String>,
+    errors: Vec<AsmError>,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Self {
+            line: 0,
+            addr: PROGRAM_START_ADDR as usize,
+            statements: Vec::new(),
+            labels: BTreeMap::new(),
+            constants: BTreeMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// First pass, collects labels and statements
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        // stripping comments
+        let mut line = match line.find(['#', ';']) {
+            Some(i) => &line[..i],
+            None => line,
+        }.trim();
+        line = strip_listing_columns(line);
+
+        // labels
+        while let Some((name, rest)) = line.split_once(':') {
+            let name = name.trim();
+            if !is_identifier(name) {
+                break;
+            }
+            self.define(name)?;
+            if self.addr >= MEM_SIZE {
+                return Err(format!("Label '{name}' is past the end of memory"));
+            }
+            self.labels.insert(name.to_string(), self.addr as u16);
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        // constants
+        if let Some((name, value)) = line.split_once('=') {
+            let name = name.trim();
+            if !is_identifier(name) {
+                return Err(format!("Invalid constant name '{name}'"));
+            }
+            self.define(name)?;
+            self.constants.insert(name.to_string(), value.trim().to_string());
+            return Ok(());
+        }
+
+        let (first, rest) = match line.split_once(char::is_whitespace) {
+            Some((first, rest)) => (first, rest.trim()),
+            None => (line, ""),
+        };
+
+        // raw hex words
+        if is_hex_word(first) {
+            let words = line
+                .split_whitespace()
+                .map(|word| {
+                    if is_hex_word(word) {
+                        Ok(u16::from_str_radix(word, 16).unwrap())
+                    } else {
+                        Err(format!("Expected a 4 digit hex word, found '{word}'"))
+                    }
+                })
+                .collect::<Result<Vec<u16>, String>>()?;
+            return self.push(2 * words.len(), Statement::Raw(words));
+        }
+
+        let operands: Vec<String> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',')
+                .map(|operand| operand.trim().to_string())
+                .collect()
+        };
+        if operands.iter().any(|operand| operand.is_empty()) {
+            return Err("Empty operand".to_string());
+        }
+
+        match first.to_ascii_lowercase().as_str() {
+            "db" => self.push(operands.len(), Statement::Bytes(operands)),
+            "dw" => self.push(2 * operands.len(), Statement::Words(operands)),
+            "org" => {
+                let [addr] = operands.as_slice() else {
+                    return Err("Expected one operand for 'org'".to_string());
+                };
+                let addr = self.eval(addr, 0)?;
+                if addr < (self.addr as i64) {
+                    return Err(format!("Can't move the output address back to {addr:#X}"));
+                }
+                if addr >= MEM_SIZE as i64 {
+                    return Err(format!("Can't move the output address to {addr:#X}, it's out of memory"));
+                }
+                self.addr = addr as usize;
+                Ok(())
+            }
+            _ => {
+                // `LD I, LONG addr` is the only 4 byte instruction
+                let size = if operands.iter().any(|operand| matches!(classify(operand), Operand::Long(_))) {
+                    4
+                } else {
+                    2
+                };
+                let statement = Statement::Instruction {
+                    mnemonic: first.to_ascii_uppercase(),
+                    operands,
+                };
+                self.push(size, statement)
+            }
+        }
+    }
+
+    /// Makes sure a label or constant isn't defined twice
+    fn define(&self, name: &str) -> Result<(), String> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            Err(format!("'{name}' is already defined"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn push(&mut self, size: usize, statement: Statement) -> Result<(), String> {
+        // ending right at the end of memory is fine
+        if self.addr + size > MEM_SIZE {
+            return Err("Program doesn't fit in memory".to_string());
+        }
+        self.statements.push((self.line, self.addr as u16, statement));
+        self.addr += size;
+        Ok(())
+    }
+
+    /// Second pass, encodes every statement now that all labels are known
+    fn emit(mut self) -> Result<Assembly, Vec<AsmError>> {
+        let mut rom = Vec::new();
+        let mut lines = Vec::new();
+        let statements = std::mem::take(&mut self.statements);
+        for (line, addr, statement) in statements.iter() {
+            let bytes = match self.encode(statement) {
+                Ok(bytes) => bytes,
+                Err(message) => {
+                    self.errors.push(AsmError { line: line + 1, message });
+                    continue;
+                }
+            };
+            let start = (addr - PROGRAM_START_ADDR) as usize;
+            if rom.len() < start {
+                rom.resize(start, 0);
+            }
+            rom.extend_from_slice(&bytes);
+            lines.push((line + 1, *addr, bytes.len()));
+        }
+
+        if self.errors.is_empty() {
+            Ok(Assembly { rom, labels: self.labels, lines })
+        } else {
+            self.errors.sort_by_key(|error| error.line);
+            Err(self.errors)
+        }
+    }
+
+    fn encode(&self, statement: &Statement) -> Result<Vec<u8>, String> {
+        match statement {
+            Statement::Instruction { mnemonic, operands } => {
+                let operands: Vec<Operand> = operands
+                    .iter()
+                    .map(|operand| classify(operand))
+                    .collect();
+                let instruction = self.encode_instruction(mnemonic, &operands)?;
+                Ok(instruction.to_bytes())
+            }
+            Statement::Bytes(values) => {
+                values
+                    .iter()
+                    .map(|value| self.byte(value))
+                    .collect()
+            }
+            Statement::Words(values) => {
+                let mut bytes = Vec::new();
+                for value in values {
+                    let value = self.eval_in_range(value, -0x8000, 0xffff)?;
+                    bytes.extend_from_slice(&(value as u16).to_be_bytes());
+                }
+                Ok(bytes)
+            }
+            Statement::Raw(words) => {
+                Ok(
+                    words
+                        .iter()
+                        .flat_map(|word| word.to_be_bytes())
+                        .collect()
+                )
+            }
+        }
+    }
+
+    fn encode_instruction(&self, mnemonic: &str, operands: &[Operand]) -> Result<Instruction, String> {
+        use Operand::*;
+        let instruction = match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Expr(n)]) => Instruction::Scd(self.eval_in_range(n, 0, 0xf)? as u8),
+            ("SCU", [Expr(n)]) => Instruction::Scu(self.eval_in_range(n, 0, 0xf)? as u8),
+            ("SCR", []) => Instruction::Scr,
+            ("SCL", []) => Instruction::Scl,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Low,
+            ("HIGH", []) => Instruction::High,
+            ("SYS", [Expr(a)]) => Instruction::Sys(self.addr(a)?),
+            ("JP", [Expr(a)]) => Instruction::Jp(self.addr(a)?),
+            ("JP", [Reg(0), Expr(a)]) => Instruction::JpV0(self.addr(a)?),
+            // BXNN with the jump_vx quirk, X is both the register and the
+            // high nibble of the address
+            ("JP", [Reg(x), Expr(a)]) => {
+                let addr = self.addr(a)?;
+                if addr >> 8 != *x as u16 {
+                    return Err(format!("'JP V{x:X}' can only jump to 0x{x:X}00-0x{x:X}FF, got {addr:#05X}"));
+                }
+                Instruction::JpV0(addr)
+            }
+            ("CALL", [Expr(a)]) => Instruction::Call(self.addr(a)?),
+            ("SE", [Reg(x), Reg(y)]) => Instruction::SeReg(*x, *y),
+            ("SE", [Reg(x), Expr(b)]) => Instruction::SeImm(*x, self.byte(b)?),
+            ("SNE", [Reg(x), Reg(y)]) => Instruction::SneReg(*x, *y),
+            ("SNE", [Reg(x), Expr(b)]) => Instruction::SneImm(*x, self.byte(b)?),
+            ("LD", [Reg(x), Reg(y)]) => Instruction::LdReg(*x, *y),
+            ("LD", [Reg(x), Expr(b)]) => Instruction::LdImm(*x, self.byte(b)?),
+            ("LD", [I, Expr(a)]) => Instruction::LdI(self.addr(a)?),
+            ("LD", [I, Long(a)]) => Instruction::LdILong(self.eval_in_range(a, 0, 0xffff)? as u16),
+            ("LD", [Reg(x), DT]) => Instruction::LdVxDt(*x),
+            ("LD", [Reg(x), K]) => Instruction::LdVxK(*x),
+            ("LD", [DT, Reg(x)]) => Instruction::LdDtVx(*x),
+            ("LD", [ST, Reg(x)]) => Instruction::LdStVx(*x),
+            ("LD", [F, Reg(x)]) => Instruction::LdFVx(*x),
+            ("LD", [HF, Reg(x)]) => Instruction::LdHfVx(*x),
+            ("LD", [B, Reg(x)]) => Instruction::LdBVx(*x),
+            ("LD", [R, Reg(x)]) => Instruction::LdRVx(*x),
+            ("LD", [Reg(x), R]) => Instruction::LdVxR(*x),
+            ("LD", [IndirectI, Reg(x)]) => Instruction::LdIVx(*x),
+            ("LD", [Reg(x), IndirectI]) => Instruction::LdVxI(*x),
+            ("ADD", [Reg(x), Reg(y)]) => Instruction::AddReg(*x, *y),
+            ("ADD", [Reg(x), Expr(b)]) => Instruction::AddImm(*x, self.byte(b)?),
+            ("ADD", [I, Reg(x)]) => Instruction::AddIVx(*x),
+            ("OR", [Reg(x), Reg(y)]) => Instruction::Or(*x, *y),
+            ("AND", [Reg(x), Reg(y)]) => Instruction::And(*x, *y),
+            ("XOR", [Reg(x), Reg(y)]) => Instruction::Xor(*x, *y),
+            ("SUB", [Reg(x), Reg(y)]) => Instruction::Sub(*x, *y),
+            ("SUBN", [Reg(x), Reg(y)]) => Instruction::Subn(*x, *y),
+            // the single operand shifts use VX as the source too, so they
+            // behave the same no matter which register the interpreter shifts
+            ("SHR", [Reg(x)]) => Instruction::Shr(*x, *x),
+            ("SHR", [Reg(x), Reg(y)]) => Instruction::Shr(*x, *y),
+            ("SHL", [Reg(x)]) => Instruction::Shl(*x, *x),
+            ("SHL", [Reg(x), Reg(y)]) => Instruction::Shl(*x, *y),
+            ("RND", [Reg(x), Expr(b)]) => Instruction::Rnd(*x, self.byte(b)?),
+            ("DRW", [Reg(x), Reg(y), Expr(n)]) => {
+                Instruction::Drw(*x, *y, self.eval_in_range(n, 0, 0xf)? as u8)
+            }
+            ("SKP", [Reg(x)]) => Instruction::Skp(*x),
+            ("SKNP", [Reg(x)]) => Instruction::Sknp(*x),
+            ("SAVE", [Reg(x), Reg(y)]) => Instruction::SaveRange(*x, *y),
+            ("LOAD", [Reg(x), Reg(y)]) => Instruction::LoadRange(*x, *y),
+            ("PLANE", [Expr(n)]) => Instruction::Plane(self.eval_in_range(n, 0, 0x3)? as u8),
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [Reg(x)]) => Instruction::Pitch(*x),
+            _ => {
+                return Err(if is_mnemonic(mnemonic) {
+                    format!("Invalid operands for '{mnemonic}'")
+                } else {
+                    format!("Unknown instruction '{mnemonic}'")
+                });
+            }
+        };
+        Ok(instruction)
+    }
+
+    fn addr(&self, expr: &str) -> Result<u16, String> {
+        Ok(self.eval_in_range(expr, 0, 0xfff)? as u16)
+    }
+
+    /// Bytes can be given signed too, e.g. `ADD V0, -1`
+    fn byte(&self, expr: &str) -> Result<u8, String> {
+        Ok(self.eval_in_range(expr, -0x80, 0xff)? as u8)
+    }
+
+    fn eval_in_range(&self, expr: &str, min: i64, max: i64) -> Result<i64, String> {
+        let value = self.eval(expr, 0)?;
+        if value < min || value > max {
+            return Err(format!("'{expr}' is out of range ({value}, expected {min} to {max})"));
+        }
+        Ok(value)
+    }
+
+    /// Evaluates a `+`/`-` expression of numbers, labels and constants
+    fn eval(&self, expr: &str, depth: usize) -> Result<i64, String> {
+        if depth > 16 {
+            return Err(format!("Constant '{expr}' is defined in terms of itself"));
+        }
+
+        let mut total = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+        for c in expr.chars() {
+            match c {
+                // unary signs
+                '-' if term.trim().is_empty() => {
+                    sign = -sign;
+                }
+                '+' if term.trim().is_empty() => {}
+                '+' | '-' => {
+                    total = add_term(total, sign, self.eval_term(term.trim(), depth)?, expr)?;
+                    term.clear();
+                    sign = if c == '-' { -1 } else { 1 };
+                }
+                _ => {
+                    term.push(c);
+                }
+            }
+        }
+        if term.trim().is_empty() {
+            return Err(format!("Invalid expression '{expr}'"));
+        }
+        add_term(total, sign, self.eval_term(term.trim(), depth)?, expr)
+    }
+
+    fn eval_term(&self, term: &str, depth: usize) -> Result<i64, String> {
+        if let Some(value) = parse_number(term) {
+            return Ok(value);
+        }
+        if let Some(addr) = self.labels.get(term) {
+            return Ok(*addr as i64);
+        }
+        if let Some(value) = self.constants.get(term) {
+            return self.eval(value, depth + 1);
+        }
+        if is_identifier(term) {
+            Err(format!("Undefined label or constant '{term}'"))
+        } else {
+            Err(format!("Invalid number '{term}'"))
+        }
+    }
+}
+
+fn classify(operand: &str) -> Operand {
+    let upper = operand.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DT,
+        "ST" => Operand::ST,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "HF" => Operand::HF,
+        "B" => Operand::B,
+        "R" => Operand::R,
+        _ if upper.starts_with("LONG ") => Operand::Long(operand[5..].trim().to_string()),
+        _ => {
+            match upper.strip_prefix('V') {
+                Some(x) if x.len() == 1 => {
+                    match u8::from_str_radix(x, 16) {
+                        Ok(x) => Operand::Reg(x),
+                        Err(_) => Operand::Expr(operand.to_string()),
+                    }
+                }
+                _ => Operand::Expr(operand.to_string()),
+            }
+        }
+    }
+}
+
+/// Adds `sign * value` to `total`, for [`Assembler::eval`]
+fn add_term(total: i64, sign: i64, value: i64, expr: &str) -> Result<i64, String> {
+    sign.checked_mul(value)
+        .and_then(|value| total.checked_add(value))
+        .ok_or_else(|| format!("'{expr}' overflows"))
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let s = s.to_ascii_lowercase();
+    // `from_str_radix` takes a sign too, which is the expression's business
+    let (digits, radix) = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix('$')) {
+        (hex, 16)
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        (bin, 2)
+    } else if s.starts_with(|c: char| c.is_ascii_digit()) {
+        (s.as_str(), 10)
+    } else {
+        return None;
+    };
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Skips the `0x200  6A05  ` address and raw bytes columns in front of a
+/// disassembly listing's lines, anything else is returned unchanged
+fn strip_listing_columns(line: &str) -> &str {
+    let is_hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit());
+    let Some((addr, rest)) = line.split_once(char::is_whitespace) else {
+        return line;
+    };
+    let Some((raw, rest)) = rest.trim_start().split_once(char::is_whitespace) else {
+        return line;
+    };
+    let is_addr = addr.strip_prefix("0x").is_some_and(is_hex);
+    let is_raw = is_hex(raw) && raw.len() % 2 == 0 && raw.len() <= 8;
+    if is_addr && is_raw {
+        rest.trim()
+    } else {
+        line
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') &&
+        s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_hex_word(s: &str) -> bool {
+    s.len() == 4 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_mnemonic(s: &str) -> bool {
+    [
+        "CLS", "RET", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB",
+        "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "SCU", "SAVE", "LOAD", "PLANE", "AUDIO", "PITCH",
+    ].contains(&s)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::{ disassemble, disassemble_linear, DisasmLine };
+
+    fn examples() -> impl Iterator<Item = std::path::PathBuf> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+        let mut roms: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ch8"))
+            .collect();
+        roms.sort();
+        roms.into_iter()
+    }
+
+    fn listing(lines: Vec<DisasmLine>) -> String {
+        lines
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn example_sources_match_their_roms() {
+        let mut sources = 0;
+        for rom_path in examples() {
+            let source_path = rom_path
+                .parent()
+                .unwrap()
+                .join("src")
+                .join(rom_path.file_stem().unwrap())
+                .with_extension("asm");
+            let Ok(source) = fs::read_to_string(&source_path) else {
+                continue;
+            };
+            let assembly = assemble(&source).unwrap();
+            assert_eq!(assembly.rom, fs::read(&rom_path).unwrap(), "{}", source_path.display());
+            sources += 1;
+        }
+        assert!(sources > 0);
+    }
+
+    #[test]
+    fn disassembly_assembles_back() {
+        for rom_path in examples() {
+            let rom = fs::read(&rom_path).unwrap();
+            for lines in [disassemble(&rom), disassemble_linear(&rom)] {
+                let assembly = assemble(&listing(lines)).unwrap();
+                assert_eq!(assembly.rom, rom, "{}", rom_path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn labels_constants_and_data() {
+        let source = "\
+            start:  LD V0, count      ; forward references are fine
+                    JP end
+            count = 3 + 2
+                    org 0x208
+            end:    db 0x3C, $42, 0b1
+                    dw sprite - start
+            sprite: 6101 6202
+        ";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(
+            assembly.rom,
+            [0x60, 0x05, 0x12, 0x08, 0, 0, 0, 0, 0x3c, 0x42, 0x01, 0x00, 0x0d, 0x61, 0x01, 0x62, 0x02]
+        );
+        assert_eq!(assembly.labels["end"], 0x208);
+        assert_eq!(assembly.labels["sprite"], 0x20d);
+        assert_eq!(assembly.lines[0], (1, 0x200, 2));
+    }
+
+    #[test]
+    fn jump_with_offset_register() {
+        assert_eq!(assemble("JP V0, 0x345").unwrap().rom, [0xb3, 0x45]);
+        assert_eq!(assemble("JP V3, 0x345").unwrap().rom, [0xb3, 0x45]);
+        assert!(assemble("JP V3, 0x245").is_err());
+    }
+
+    #[test]
+    fn numbers() {
+        for (s, value) in [("42", 42), ("0x1F", 0x1f), ("$ff", 0xff), ("0b101", 5)] {
+            assert_eq!(parse_number(s), Some(value), "{s}");
+        }
+        // signs belong to the expression, not the number
+        for s in ["0x-1", "0x+1", "$-1", "0b-1", "0b+1", "1+", "0x", "0b2", "x1"] {
+            assert_eq!(parse_number(s), None, "{s}");
+        }
+        assert_eq!(assemble("db -0x1, 0x0 - 1").unwrap().rom, [0xff, 0xff]);
+        assert!(assemble("db 0x-1").is_err());
+    }
+
+    #[test]
+    fn overflowing_expressions_are_errors() {
+        let max = i64::MAX;
+        for source in [
+            format!("dw {max} + 1"),
+            format!("dw -{max} - 2"),
+            format!("big = {max}\ndw big + big"),
+        ] {
+            let errors = assemble(&source).unwrap_err();
+            assert!(errors[0].message.ends_with("overflows"), "{source}: {errors:?}");
+        }
+    }
+
+    #[test]
+    fn programs_can_fill_memory() {
+        let assembly = assemble("org 0xfffe\nend: dw 0x1234").unwrap();
+        assert_eq!(assembly.rom.len(), 0x10000 - 0x200);
+        assert_eq!(assembly.rom[0xfffe - 0x200..], [0x12, 0x34]);
+        assert_eq!(assembly.lines, [(2, 0xfffe, 2)]);
+
+        for (source, message) in [
+            ("org 0xfffe\ndw 1, 2", "Program doesn't fit in memory"),
+            ("org 0xfffe\ndw 1\nafter:", "Label 'after' is past the end of memory"),
+            ("org 0x10000", "Can't move the output address to 0x10000, it's out of memory"),
+            ("org 0x300\norg 0x2ff", "Can't move the output address back to 0x2FF"),
+        ] {
+            assert_eq!(assemble(source).unwrap_err()[0].message, message, "{source}");
+        }
+    }
+
+    #[test]
+    fn errors_point_at_their_lines() {
+        let errors = assemble("CLS\nLD V0, 0x100\nFOO\nJP nowhere\n").unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, [2, 3, 4]);
+    }
+}
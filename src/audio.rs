@@ -0,0 +1,111 @@
+use std::time::Duration;
+use rodio::{ OutputStream, Sink };
+use rodio::source::{ SineWave, Source };
+
+use crate::*;
+
+/// Rate the audio pattern is resampled to
+const OUTPUT_SAMPLE_RATE: u32 = 48000;
+const VOLUME: f32 = 0.1;
+
+/// Tone played while the sound timer is running
+pub struct Buzzer {
+    // the stream has to be kept alive for the sink to make any noise
+    _stream: OutputStream,
+    sink: Sink,
+    /// Pattern and sample rate currently queued, `None` for the plain tone
+    sound: Option<([u8; 16], f32)>,
+}
+
+impl Buzzer {
+    pub fn create() -> Self {
+        println_debug!("Configuring buzzer");
+        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
+        let sink = Sink::try_new(&stream_handle).unwrap();
+        sink.pause();
+        sink.append(SineWave::new(BUZZER_FREQ).amplify(VOLUME).repeat_infinite());
+        Self { _stream, sink, sound: None }
+    }
+
+    /// Starts or stops the tone
+    pub fn set_active(&self, active: bool) {
+        if active && self.sink.is_paused() {
+            self.sink.play();
+        } else if !active && !self.sink.is_paused() {
+            self.sink.pause();
+        }
+    }
+
+    /// Switches to looping an XO-CHIP audio pattern at the given sample rate,
+    /// or back to the plain tone
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, sample_rate: f32) {
+        let sound = pattern.map(|pattern| (*pattern, sample_rate));
+        if sound == self.sound {
+            return;
+        }
+        self.sound = sound;
+
+        let paused = self.sink.is_paused();
+        // clearing leaves the sink paused
+        self.sink.clear();
+        match sound {
+            Some((pattern, sample_rate)) => {
+                self.sink.append(PatternSource::new(pattern, sample_rate));
+            }
+            None => {
+                self.sink.append(SineWave::new(BUZZER_FREQ).amplify(VOLUME).repeat_infinite());
+            }
+        }
+        if !paused {
+            self.sink.play();
+        }
+    }
+}
+
+/// Endlessly loops the 128 1-bit samples of an audio pattern
+struct PatternSource {
+    pattern: [u8; 16],
+    /// Pattern samples per output sample
+    step: f32,
+    /// Position in the pattern, in samples
+    position: f32,
+}
+
+impl PatternSource {
+    fn new(pattern: [u8; 16], sample_rate: f32) -> Self {
+        Self {
+            pattern,
+            step: sample_rate / (OUTPUT_SAMPLE_RATE as f32),
+            position: 0.0,
+        }
+    }
+}
+
+impl Iterator for PatternSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let i = self.position as usize;
+        let bit = (self.pattern[i / 8] >> (7 - (i % 8))) & 0x1;
+        self.position = (self.position + self.step) % 128.0;
+        Some(if bit == 1 { VOLUME } else { -VOLUME })
+    }
+}
+
+impl Source for PatternSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        OUTPUT_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{ self, Write };
+use std::str::FromStr;
+
+use crate::*;
+
+/// File format of a [`Capture`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// Animated GIF, frames that don't change the screen are merged
+    Gif,
+    /// Uncompressed YUV4MPEG2 video at exactly 60 frames per second
+    Y4m,
+}
+
+impl CaptureFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CaptureFormat::Gif => "gif",
+            CaptureFormat::Y4m => "y4m",
+        }
+    }
+}
+
+impl fmt::Display for CaptureFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl FromStr for CaptureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gif" => Ok(CaptureFormat::Gif),
+            "y4m" => Ok(CaptureFormat::Y4m),
+            _ => Err("unknown capture format, expected one of: gif, y4m".to_string()),
+        }
+    }
+}
+
+/// Records the screen into an animation, one frame per 60hz tick
+///
+/// The canvas is always the size of the high resolution screen (times
+/// `scale`), with low resolution frames scaled up to fill it, so switching
+/// modes halfway through is fine.
+pub struct Capture<W: Write> {
+    out: W,
+    format: CaptureFormat,
+    scale: usize,
+    palette: [[u8; 4]; 4],
+    /// GIF frame waiting for the screen to change, so its length is known
+    pending: Option<(Vec<u8>, u64)>,
+    /// Ticks captured so far
+    ticks: u64,
+    /// Ticks covered by the GIF frames written so far
+    ticks_written: u64,
+}
+
+impl<W: Write> Capture<W> {
+    /// Starts a capture, writing the file header to `out`
+    ///
+    /// `palette` colours pixels by their value (unlit, first plane, second
+    /// plane, both) as RGBA, with the alpha ignored.
+    pub fn new(mut out: W, format: CaptureFormat, scale: usize, palette: &[[u8; 4]; 4]) -> io::Result<Self> {
+        let scale = scale.max(1);
+        let (width, height) = (HIRES_SCREEN_W * scale, HIRES_SCREEN_H * scale);
+        match format {
+            CaptureFormat::Gif => {
+                out.write_all(b"GIF89a")?;
+                out.write_all(&(width as u16).to_le_bytes())?;
+                out.write_all(&(height as u16).to_le_bytes())?;
+                // global colour table of 4 entries, 8 bits per channel
+                out.write_all(&[0xf1, 0, 0])?;
+                for color in palette {
+                    out.write_all(&color[..3])?;
+                }
+                // loop forever
+                out.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+            }
+            CaptureFormat::Y4m => {
+                writeln!(out, "YUV4MPEG2 W{width} H{height} F{REFRESH_RATE}:1 Ip A1:1 C444")?;
+            }
+        }
+        Ok(Self {
+            out,
+            format,
+            scale,
+            palette: *palette,
+            pending: None,
+            ticks: 0,
+            ticks_written: 0,
+        })
+    }
+
+    /// Number of 60hz ticks captured
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Adds the screen as it is at a tick
+    pub fn add_frame(&mut self, pixel_buf: &PixelBuf, hires: bool) -> io::Result<()> {
+        let frame = self.scale_frame(pixel_buf, hires);
+        self.ticks += 1;
+        match self.format {
+            CaptureFormat::Gif => {
+                match &mut self.pending {
+                    Some((pending, ticks)) if *pending == frame => {
+                        *ticks += 1;
+                    }
+                    _ => {
+                        if let Some((pending, ticks)) = self.pending.take() {
+                            self.write_gif_frame(&pending, ticks)?;
+                        }
+                        self.pending = Some((frame, 1));
+                    }
+                }
+                Ok(())
+            }
+            CaptureFormat::Y4m => self.write_y4m_frame(&frame),
+        }
+    }
+
+    /// Writes out anything still pending and the file trailer
+    pub fn finish(mut self) -> io::Result<W> {
+        if let CaptureFormat::Gif = self.format {
+            if let Some((pending, ticks)) = self.pending.take() {
+                self.write_gif_frame(&pending, ticks)?;
+            }
+            self.out.write_all(&[0x3b])?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Palette indices of every canvas pixel
+    fn scale_frame(&self, pixel_buf: &PixelBuf, hires: bool) -> Vec<u8> {
+        let scale = if hires { self.scale } else { self.scale * 2 };
+        let (width, height) = (HIRES_SCREEN_W * self.scale, HIRES_SCREEN_H * self.scale);
+        let mut frame = Vec::with_capacity(width * height);
+        for y in 0..height {
+            frame.extend((0..width).map(|x| pixel_buf[y / scale][x / scale] & 0x3));
+        }
+        frame
+    }
+
+    fn write_gif_frame(&mut self, frame: &[u8], ticks: u64) -> io::Result<()> {
+        // GIF delays are in 1/100 s, working them out from the running total
+        // keeps the timing from drifting
+        let start = self.ticks_written * 100 / (REFRESH_RATE as u64);
+        self.ticks_written += ticks;
+        let end = self.ticks_written * 100 / (REFRESH_RATE as u64);
+        let delay = (end - start).min(u16::MAX as u64) as u16;
+
+        // graphic control extension, with the delay
+        self.out.write_all(&[0x21, 0xf9, 0x04, 0x00])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        // image descriptor, covering the whole canvas
+        let (width, height) = (HIRES_SCREEN_W * self.scale, HIRES_SCREEN_H * self.scale);
+        self.out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        self.out.write_all(&(width as u16).to_le_bytes())?;
+        self.out.write_all(&(height as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00])?;
+
+        self.out.write_all(&[GIF_MIN_CODE_SIZE])?;
+        for block in lzw_encode(frame).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+
+    fn write_y4m_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        // BT.601 studio range, Y, U and V of each palette colour
+        let mut planes = [[0u8; 4]; 3];
+        for (i, color) in self.palette.iter().enumerate() {
+            let (r, g, b) = (color[0] as f32, color[1] as f32, color[2] as f32);
+            planes[0][i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+            planes[1][i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+            planes[2][i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        for plane in planes.iter() {
+            let bytes: Vec<u8> = frame
+                .iter()
+                .map(|&pixel| plane[pixel as usize])
+                .collect();
+            self.out.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bits per pixel of the 4 colour GIF palette, as far as LZW is concerned
+const GIF_MIN_CODE_SIZE: u8 = 2;
+
+/// Compresses palette indices into GIF's variable width LZW codes
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let clear_code: u16 = 1 << GIF_MIN_CODE_SIZE;
+    let end_code = clear_code + 1;
+
+    let mut out = Vec::new();
+    let (mut bit_buf, mut bit_count) = (0u32, 0u32);
+    let mut emit = |code: u16, code_size: u32, out: &mut Vec<u8>| {
+        bit_buf |= (code as u32) << bit_count;
+        bit_count += code_size;
+        while bit_count >= 8 {
+            out.push(bit_buf as u8);
+            bit_buf >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = (GIF_MIN_CODE_SIZE + 1) as u32;
+    let mut next_code = end_code + 1;
+    emit(clear_code, code_size, &mut out);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        emit(end_code, code_size, &mut out);
+        if bit_count > 0 {
+            out.push(bit_buf as u8);
+        }
+        return out;
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = dict.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        emit(prefix, code_size, &mut out);
+        if next_code < 4095 {
+            dict.insert((prefix, index), next_code);
+            if next_code == (1 << code_size) {
+                code_size += 1;
+            }
+            next_code += 1;
+        } else {
+            // table is full, start over
+            emit(clear_code, code_size, &mut out);
+            dict.clear();
+            code_size = (GIF_MIN_CODE_SIZE + 1) as u32;
+            next_code = end_code + 1;
+        }
+        prefix = index as u16;
+    }
+    emit(prefix, code_size, &mut out);
+    emit(end_code, code_size, &mut out);
+    if bit_count > 0 {
+        out.push(bit_buf as u8);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALETTE: [[u8; 4]; 4] = [[0, 0, 0, 255], [255, 255, 255, 255], [255, 0, 0, 255], [0, 0, 255, 255]];
+
+    /// Decodes GIF LZW data, also returning the width of every code read and
+    /// the number of clear codes
+    fn lzw_decode(data: &[u8]) -> (Vec<u8>, Vec<u32>, usize) {
+        let clear_code = 1 << GIF_MIN_CODE_SIZE;
+        let end_code = clear_code + 1;
+        let initial: Vec<Vec<u8>> = (0..=end_code).map(|code| vec![code as u8]).collect();
+
+        let (mut out, mut widths, mut clears) = (vec![], vec![], 0);
+        let mut dict = initial.clone();
+        let mut code_size = (GIF_MIN_CODE_SIZE + 1) as u32;
+        let mut prev: Option<usize> = None;
+        let mut pos = 0;
+        loop {
+            let mut code = 0;
+            for bit in 0..code_size as usize {
+                let byte = data[(pos + bit) / 8];
+                code |= (((byte >> ((pos + bit) % 8)) & 1) as usize) << bit;
+            }
+            pos += code_size as usize;
+            widths.push(code_size);
+
+            if code == clear_code {
+                clears += 1;
+                dict = initial.clone();
+                code_size = (GIF_MIN_CODE_SIZE + 1) as u32;
+                prev = None;
+                continue;
+            }
+            if code == end_code {
+                break;
+            }
+            let entry = if code < dict.len() {
+                dict[code].clone()
+            } else {
+                // the code being defined by this very step
+                assert_eq!(code, dict.len());
+                let prev = &dict[prev.unwrap()];
+                [prev.as_slice(), &prev[..1]].concat()
+            };
+            out.extend_from_slice(&entry);
+            if let Some(prev) = prev {
+                dict.push([dict[prev].as_slice(), &entry[..1]].concat());
+                if dict.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+            prev = Some(code);
+        }
+        // nothing but padding after the end code
+        assert_eq!(data.len(), pos.div_ceil(8));
+        (out, widths, clears)
+    }
+
+    #[test]
+    fn lzw_code_size_grows() {
+        // clear, 0, 1 and 2, then 8 is defined and the codes are 4 bits
+        let (indices, widths, clears) = lzw_decode(&lzw_encode(&[0, 1, 2, 3]));
+        assert_eq!(indices, [0, 1, 2, 3]);
+        assert_eq!(widths, [3, 3, 3, 3, 4, 4]);
+        assert_eq!(clears, 1);
+
+        let (indices, widths, _) = lzw_decode(&lzw_encode(&[]));
+        assert!(indices.is_empty());
+        assert_eq!(widths, [3, 3]);
+    }
+
+    #[test]
+    fn lzw_clears_a_full_table() {
+        // noise fills the 4096 code table a few times over
+        let mut rng = crate::rng::Rng::new(1);
+        let noise: Vec<u8> = (0..50000).map(|_| rng.next_u8() & 0x3).collect();
+        let (indices, widths, clears) = lzw_decode(&lzw_encode(&noise));
+        assert_eq!(indices, noise);
+        assert!(clears > 2, "{clears} clears");
+        assert_eq!(widths.iter().max(), Some(&12));
+        // every clear starts over at 3 bits
+        for pair in widths.windows(2).filter(|pair| pair[0] == 12) {
+            assert!(pair[1] == 12 || pair[1] == 3, "{pair:?}");
+        }
+    }
+
+    #[test]
+    fn gif_frames_and_delays() {
+        let blank = [[0; HIRES_SCREEN_W]; HIRES_SCREEN_H];
+        let mut lit = blank;
+        lit[0][0] = 1;
+        lit[31][63] = 2;
+
+        let mut capture = Capture::new(Vec::new(), CaptureFormat::Gif, 1, &PALETTE).unwrap();
+        for pixel_buf in [&blank, &blank, &blank, &lit] {
+            capture.add_frame(pixel_buf, false).unwrap();
+        }
+        assert_eq!(capture.ticks(), 4);
+        let gif = capture.finish().unwrap();
+
+        assert_eq!(gif[..6], *b"GIF89a");
+        assert_eq!(gif[6..13], [128, 0, 64, 0, 0xf1, 0, 0]);
+        let palette: Vec<u8> = PALETTE.iter().flat_map(|color| color[..3].to_vec()).collect();
+        assert_eq!(gif[13..25], palette);
+        let mut rest = &gif[25 + 19..];
+        assert_eq!(gif[25..25 + 19], *b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+        // the 3 blank ticks are one frame, 5/100 s long, then 1/100 s more
+        // brings the total to 4/60 s
+        for (delay, pixel_buf) in [(5, &blank), (1, &lit)] {
+            assert_eq!(rest[..8], [0x21, 0xf9, 0x04, 0x00, delay, 0, 0, 0]);
+            assert_eq!(rest[8..18], [0x2c, 0, 0, 0, 0, 128, 0, 64, 0, 0]);
+            assert_eq!(rest[18], GIF_MIN_CODE_SIZE);
+            rest = &rest[19..];
+            let mut data = vec![];
+            while rest[0] != 0 {
+                let len = rest[0] as usize;
+                data.extend_from_slice(&rest[1..1 + len]);
+                rest = &rest[1 + len..];
+            }
+            rest = &rest[1..];
+
+            let (indices, _, _) = lzw_decode(&data);
+            let expected: Vec<u8> = (0..HIRES_SCREEN_H * HIRES_SCREEN_W)
+                .map(|i| pixel_buf[i / HIRES_SCREEN_W / 2][i % HIRES_SCREEN_W / 2])
+                .collect();
+            assert_eq!(indices, expected);
+        }
+        assert_eq!(rest, [0x3b]);
+    }
+
+    #[test]
+    fn y4m_header_and_frames() {
+        let mut lit = [[0; HIRES_SCREEN_W]; HIRES_SCREEN_H];
+        lit[0][0] = 1;
+
+        let mut capture = Capture::new(Vec::new(), CaptureFormat::Y4m, 2, &PALETTE).unwrap();
+        capture.add_frame(&lit, false).unwrap();
+        capture.add_frame(&lit, true).unwrap();
+        let y4m = capture.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W256 H128 F60:1 Ip A1:1 C444\n";
+        assert_eq!(y4m[..header.len()], *header);
+        let frame_size = b"FRAME\n".len() + 3 * 256 * 128;
+        assert_eq!(y4m.len(), header.len() + 2 * frame_size);
+
+        for (i, lit_size) in [(0, 4), (1, 2)] {
+            let frame = &y4m[header.len() + i * frame_size..][..frame_size];
+            assert_eq!(frame[..6], *b"FRAME\n");
+            let (y, uv) = frame[6..].split_at(256 * 128);
+            // white then black, the low resolution pixel 4x4 and the high
+            // resolution one 2x2
+            assert_eq!(y[..lit_size], vec![235; lit_size][..]);
+            assert_eq!(y[lit_size], 16);
+            assert_eq!(y[256 * (lit_size - 1)], 235);
+            assert_eq!(y[256 * lit_size], 16);
+            assert!(uv.iter().all(|&value| value == 128));
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::*;
+
+const READ: u8 = 0x1;
+const WRITTEN: u8 = 0x2;
+
+/// Tracks which bytes of memory were executed, read and written
+///
+/// Bytes count as executed when they're fetched as (part of) an instruction,
+/// as read when they're loaded by `FX65`, `5XY3`, `DXYN` or `F002`, and as
+/// written when they're stored to by `FX33`, `FX55` or `5XY2`.
+pub struct Coverage {
+    /// Times each byte was fetched as part of an instruction
+    fetches: Vec<u64>,
+    /// [`READ`] and [`WRITTEN`] flags of each byte
+    data: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            fetches: vec![0; MEM_SIZE],
+            data: vec![0; MEM_SIZE],
+        }
+    }
+
+    /// Times the byte at `addr` was fetched as part of an instruction
+    pub fn fetches(&self, addr: u16) -> u64 {
+        self.fetches[addr as usize]
+    }
+
+    pub fn read(&self, addr: u16) -> bool {
+        self.data[addr as usize] & READ != 0
+    }
+
+    pub fn written(&self, addr: u16) -> bool {
+        self.data[addr as usize] & WRITTEN != 0
+    }
+
+    /// Disassembly of the ROM, every line marked with whether it was
+    /// executed (`x`), read (`r`) and/or written (`w`) and how often it ran
+    ///
+    /// Code the disassembler can't find but that was executed anyway is
+    /// decoded as such. `labels` are placed in front of their addresses, so
+    /// passing the ones from the [`Assembly`] of the ROM makes the listing
+    /// easier to follow.
+    pub fn listing(&self, rom: &[u8], labels: &BTreeMap<String, u16>) -> String {
+        let mut is_code = vec![false; rom.len()];
+        for line in disassemble(rom).iter().filter(|line| line.instruction.is_some()) {
+            is_code[(line.addr - PROGRAM_START_ADDR) as usize] = true;
+        }
+        let mut labels_at: BTreeMap<u16, Vec<&str>> = BTreeMap::new();
+        for (name, addr) in labels.iter() {
+            labels_at.entry(*addr).or_default().push(name);
+        }
+
+        let mut lines = Vec::new();
+        let mut i = 0;
+        while i < rom.len() {
+            let addr = PROGRAM_START_ADDR + i as u16;
+            let instruction = if is_code[i] || self.fetches(addr) > 0 { Instruction::decode_at(&rom[i..]) } else { None };
+            let size = match instruction {
+                Some(instruction) => instruction.size(),
+                // data two bytes at a time, unless that runs into code
+                None if i + 1 < rom.len() && !is_code[i + 1] && self.fetches(addr + 1) == 0 => 2,
+                None => 1,
+            };
+            let size = size.min(rom.len() - i);
+            lines.push(DisasmLine { addr, bytes: rom[i..i + size].to_vec(), instruction });
+            i += size;
+        }
+
+        let (mut instructions, mut executed) = (0, 0);
+        let (mut read, mut written) = (0, 0);
+        let mut body = String::new();
+        for line in lines.iter() {
+            for name in labels_at.get(&line.addr).into_iter().flatten() {
+                let _ = writeln!(body, "{name}:");
+            }
+            // (the last line can end right at the end of memory)
+            let start = line.addr as usize;
+            let addrs = (start..start + line.bytes.len()).map(|addr| addr as u16);
+            let fetches = self.fetches(line.addr);
+            let was_read = addrs.clone().any(|addr| self.read(addr));
+            let was_written = addrs.clone().any(|addr| self.written(addr));
+            instructions += line.instruction.is_some() as usize;
+            executed += (line.instruction.is_some() && fetches > 0) as usize;
+            read += addrs.clone().filter(|&addr| self.read(addr)).count();
+            written += addrs.filter(|&addr| self.written(addr)).count();
+
+            let marks: String = [(fetches > 0, 'x'), (was_read, 'r'), (was_written, 'w')]
+                .iter()
+                .map(|&(marked, c)| if marked { c } else { '-' })
+                .collect();
+            let count = if fetches > 0 { fetches.to_string() } else { String::new() };
+            let _ = writeln!(body, "{marks} {count:>10}  {line}");
+        }
+
+        let share = 100.0 * executed as f64 / instructions.max(1) as f64;
+        let mut listing = String::new();
+        let _ = writeln!(
+            listing,
+            "; {executed}/{instructions} instructions executed ({share:.1}%), {read} bytes read, {written} bytes written"
+        );
+        listing + &body
+    }
+
+    /// Coverage of an assembly source file in the lcov tracefile format,
+    /// for `genhtml` and editor plugins
+    ///
+    /// Every statement becomes a line, hit as often as it was executed, or
+    /// once if it's data that was read. Labels become functions.
+    pub fn lcov(&self, source_path: &str, assembly: &Assembly) -> String {
+        let mut lcov = format!("TN:\nSF:{source_path}\n");
+
+        // the first statement at each label's address
+        let mut labels: Vec<(usize, &str, u16)> = assembly.labels
+            .iter()
+            .filter_map(|(name, &addr)| {
+                let (line, _, _) = assembly.lines.iter().find(|(_, line_addr, size)| *line_addr == addr && *size > 0)?;
+                Some((*line, name.as_str(), addr))
+            })
+            .collect();
+        labels.sort();
+        for (line, name, _) in labels.iter() {
+            let _ = writeln!(lcov, "FN:{line},{name}");
+        }
+        for (_, name, addr) in labels.iter() {
+            let _ = writeln!(lcov, "FNDA:{},{name}", self.fetches(*addr));
+        }
+        let functions_hit = labels
+            .iter()
+            .filter(|(_, _, addr)| self.fetches(*addr) > 0)
+            .count();
+        let _ = writeln!(lcov, "FNF:{}\nFNH:{functions_hit}", labels.len());
+
+        let mut lines_hit = 0;
+        for &(line, addr, size) in assembly.lines.iter() {
+            let data_read = (0..size as u16).any(|i| self.read(addr.wrapping_add(i)));
+            let hits = match self.fetches(addr) {
+                0 if data_read => 1,
+                fetches => fetches,
+            };
+            lines_hit += (hits > 0) as usize;
+            let _ = writeln!(lcov, "DA:{line},{hits}");
+        }
+        let _ = writeln!(lcov, "LF:{}\nLH:{lines_hit}\nend_of_record", assembly.lines.len());
+        lcov
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for Coverage {
+    fn before_step(&mut self, emulator: &Chip8) {
+        let pc = emulator.pc();
+        let memory = emulator.memory();
+        let size = Instruction::decode_at(&memory[pc as usize..]).map_or(2, |instruction| instruction.size());
+        for i in 0..size as u16 {
+            self.fetches[pc.wrapping_add(i) as usize] += 1;
+        }
+    }
+
+    fn after_step(&mut self, emulator: &Chip8, _result: &StepResult) {
+        for access in emulator.accesses() {
+            let flag = match access.access {
+                Access::Read => READ,
+                Access::Write => WRITTEN,
+                Access::Execute => {
+                    continue;
+                }
+            };
+            for i in 0..access.len {
+                self.data[(access.addr as usize + i) % MEM_SIZE] |= flag;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::boot;
+
+    const SOURCE: &str = "\
+start:  LD I, sprite
+        CALL draw
+loop:   JP loop
+draw:   DRW V0, V0, 2
+        LD [I], V0
+        RET
+unused: CLS
+sprite: db 0xF0, 0x90
+";
+
+    /// Runs the first 8 instructions of the source
+    fn coverage() -> (Assembly, Chip8, Coverage) {
+        let assembly = assemble(SOURCE).unwrap();
+        let mut emulator = boot(700, &assembly.rom);
+        let mut coverage = Coverage::new();
+        emulator.run_cycles_observed(8, &mut coverage).unwrap();
+        (assembly, emulator, coverage)
+    }
+
+    #[test]
+    fn accesses_are_counted() {
+        let (_, _, coverage) = coverage();
+        assert_eq!((coverage.fetches(0x204), coverage.fetches(0x205)), (3, 3));
+        assert_eq!(coverage.fetches(0x20c), 0);
+        assert!(coverage.read(0x20e) && coverage.read(0x20f) && !coverage.read(0x210));
+        assert!(coverage.written(0x20e) && !coverage.written(0x20f));
+    }
+
+    #[test]
+    fn listing_marks_every_line() {
+        let (assembly, mut emulator, mut coverage) = coverage();
+        let listing = coverage.listing(&assembly.rom, &assembly.labels);
+        assert_eq!(
+            listing.lines().collect::<Vec<_>>(),
+            [
+                "; 6/6 instructions executed (100.0%), 2 bytes read, 1 bytes written",
+                "start:",
+                "x--          1  0x200  A20E  LD I, 0x20E",
+                "x--          1  0x202  2206  CALL 0x206",
+                "loop:",
+                "x--          3  0x204  1204  JP 0x204",
+                "draw:",
+                "x--          1  0x206  D002  DRW V0, V0, 2",
+                "x--          1  0x208  F055  LD [I], V0",
+                "x--          1  0x20A  00EE  RET",
+                "unused:",
+                "---             0x20C  00E0  db 0x00, 0xE0",
+                "sprite:",
+                "-rw             0x20E  F090  db 0xF0, 0x90",
+            ]
+        );
+
+        // executed code is decoded, even where the disassembler doesn't look
+        emulator.pc = 0x20c;
+        emulator.step_observed(&mut coverage).unwrap();
+        let listing = coverage.listing(&assembly.rom, &BTreeMap::new());
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "; 7/7 instructions executed (100.0%), 2 bytes read, 1 bytes written");
+        assert_eq!(lines[7], "x--          1  0x20C  00E0  CLS");
+    }
+
+    #[test]
+    fn listing_runs_to_the_end_of_memory() {
+        let assembly = assemble("JP 0x200\norg 0xfffe\ndb 0x12, 0x34").unwrap();
+        let mut coverage = Coverage::new();
+        coverage.data[0xffff] = READ | WRITTEN;
+        let listing = coverage.listing(&assembly.rom, &assembly.labels);
+        assert_eq!(listing.lines().next(), Some("; 0/1 instructions executed (0.0%), 1 bytes read, 1 bytes written"));
+        assert_eq!(listing.lines().last(), Some("-rw             0xFFFE  1234  db 0x12, 0x34"));
+    }
+
+    #[test]
+    fn lcov_maps_source_lines() {
+        let (assembly, _, coverage) = coverage();
+        let lcov = coverage.lcov("test.asm", &assembly);
+        assert_eq!(
+            lcov.lines().collect::<Vec<_>>(),
+            [
+                "TN:",
+                "SF:test.asm",
+                "FN:1,start",
+                "FN:3,loop",
+                "FN:4,draw",
+                "FN:7,unused",
+                "FN:8,sprite",
+                "FNDA:1,start",
+                "FNDA:3,loop",
+                "FNDA:1,draw",
+                "FNDA:0,unused",
+                "FNDA:0,sprite",
+                "FNF:5",
+                "FNH:3",
+                "DA:1,1",
+                "DA:2,1",
+                "DA:3,3",
+                "DA:4,1",
+                "DA:5,1",
+                "DA:6,1",
+                "DA:7,0",
+                // data that was read counts as hit once
+                "DA:8,1",
+                "LF:8",
+                "LH:7",
+                "end_of_record",
+            ]
+        );
+    }
+}
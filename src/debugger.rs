@@ -0,0 +1,635 @@
+use std::io::{ self, BufRead, Write };
+use std::sync::mpsc::{ self, Receiver, TryRecvError };
+use std::thread;
+
+use crate::*;
+
+const HELP: &str = "\
+Commands (addresses are hex, counts decimal, an empty line repeats the last
+command):
+  b, break [ADDR]       set a breakpoint at ADDR, or list breakpoints
+  d, delete ADDR        remove the breakpoint at ADDR
+  w, watch [ACCESS ADDR[-END] [log]]
+                        watch memory for reads, writes and/or execution
+                        (ACCESS is any of r, w and x), pausing or only
+                        logging on a hit, or list watchpoints
+  unwatch N             remove watchpoint N
+  s, step [N]           execute N instructions (default 1)
+  n, next               step over subroutine calls
+  c, continue           run until a breakpoint
+  pause                 stop running
+  p, print [WHAT]       print regs, i, stack and/or timers (default all)
+  l, list [ADDR] [N]    disassemble N instructions around ADDR (default PC)
+  x ADDR [LEN]          hexdump LEN bytes at ADDR (default 64)
+  h, help               show this
+  q, quit               exit, writing out any trace, profile, coverage,
+                        movie and recording";
+
+/// What came of handling a debugger's input
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Poll {
+    /// The screen might have changed
+    pub drew: bool,
+    /// The user asked to quit, so the program should wrap up and exit
+    pub quit: bool,
+}
+
+/// Interactive debugger, reading commands from stdin
+///
+/// Commands are read on a separate thread, and executed by the emulator
+/// thread whenever it polls for them, so the window keeps working while
+/// paused.
+pub struct Debugger {
+    commands: Receiver<String>,
+    paused: bool,
+    last_command: String,
+    /// Return address and stack depth of the call being stepped over
+    step_over: Option<(u16, usize)>,
+}
+
+impl Debugger {
+    /// Starts reading commands, execution starts out paused
+    pub fn start() -> Self {
+        let (command_tx, commands) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("debugger_input".to_string())
+            .spawn(move || {
+                for line in io::stdin().lock().lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if command_tx.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        if let Err(why) = spawned {
+            println!("Failed to spawn debugger input thread: {why}");
+        }
+        println!("CHIP-8 debugger, type 'help' for a list of commands");
+        Self {
+            commands,
+            paused: true,
+            last_command: String::new(),
+            step_over: None,
+        }
+    }
+
+    /// Whether execution should be held, checked before every frame
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Runs any commands that have come in since the last call
+    ///
+    /// Stepped instructions are shown to `observer`. The end of stdin counts
+    /// as quitting, as nothing could resume execution after it.
+    pub fn poll(&mut self, emulator: &mut Chip8, observer: &mut impl Observer) -> Poll {
+        let mut poll = Poll::default();
+        while !poll.quit {
+            let line = match self.commands.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => {
+                    break;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    println!();
+                    poll.quit = true;
+                    break;
+                }
+            };
+            let line = if line.trim().is_empty() { self.last_command.clone() } else { line };
+            self.last_command = line.clone();
+            if let Err(why) = self.execute(emulator, observer, &line, &mut poll) {
+                println!("{why}");
+            }
+            if self.paused && !poll.quit {
+                prompt();
+            }
+        }
+        poll
+    }
+
+    /// Reacts to the result of a frame, pausing at breakpoints, errors and
+    /// the end of the program so the machine can still be inspected
+    pub fn frame_done(&mut self, emulator: &mut Chip8, result: &Result<StepResult, Chip8Error>) {
+        print_watch_hits(emulator);
+        match result {
+            Ok(result) if result.breakpoint => {
+                let pc = emulator.pc();
+                match self.step_over {
+                    // deeper recursion of the call being stepped over
+                    Some((addr, depth)) if addr == pc && emulator.stack().len() > depth => {
+                        return;
+                    }
+                    Some((addr, _)) => {
+                        self.step_over = None;
+                        emulator.remove_breakpoint(addr);
+                        if addr != pc {
+                            println!("Breakpoint at {pc:#05X}");
+                        }
+                    }
+                    None => println!("Breakpoint at {pc:#05X}"),
+                }
+                self.pause(emulator);
+            }
+            Ok(result) if result.exited => {
+                println!("Program exited");
+                self.pause(emulator);
+            }
+            Ok(result) if result.watchpoint => self.pause(emulator),
+            Ok(_) => {}
+            Err(_) => self.pause(emulator),
+        }
+    }
+
+    fn pause(&mut self, emulator: &mut Chip8) {
+        // a step over cut short by anything else leaves its breakpoint behind
+        if let Some((addr, _)) = self.step_over.take() {
+            emulator.remove_breakpoint(addr);
+        }
+        self.paused = true;
+        print_listing(emulator, emulator.pc(), 1);
+        prompt();
+    }
+
+    fn execute(
+        &mut self,
+        emulator: &mut Chip8,
+        observer: &mut impl Observer,
+        line: &str,
+        poll: &mut Poll
+    ) -> Result<(), String> {
+        let drew = &mut poll.drew;
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        match (command, args.as_slice()) {
+            ("b" | "break", []) => {
+                let breakpoints: Vec<String> = emulator
+                    .breakpoints()
+                    .map(|addr| format!("{addr:#05X}"))
+                    .collect();
+                if breakpoints.is_empty() {
+                    println!("No breakpoints");
+                } else {
+                    println!("Breakpoints: {}", breakpoints.join(", "));
+                }
+            }
+            ("b" | "break", [addr]) => {
+                let addr = parse_addr(addr)?;
+                emulator.add_breakpoint(addr);
+                println!("Breakpoint set at {addr:#05X}");
+            }
+            ("d" | "delete", [addr]) => {
+                let addr = parse_addr(addr)?;
+                if !emulator.remove_breakpoint(addr) {
+                    return Err(format!("No breakpoint at {addr:#05X}"));
+                }
+                println!("Breakpoint deleted at {addr:#05X}");
+            }
+            ("w" | "watch", []) => {
+                if emulator.watchpoints().is_empty() {
+                    println!("No watchpoints");
+                }
+                for (i, watchpoint) in emulator.watchpoints().iter().enumerate() {
+                    let access: String = [(watchpoint.read, 'r'), (watchpoint.write, 'w'), (watchpoint.execute, 'x')]
+                        .iter()
+                        .filter(|(watched, _)| *watched)
+                        .map(|(_, c)| *c)
+                        .collect();
+                    let action = if watchpoint.pause { "pause" } else { "log" };
+                    println!("#{i}  {:#05X}-{:#05X}  {access:<3}  {action}", watchpoint.start, watchpoint.end);
+                }
+            }
+            ("w" | "watch", [access, range, options @ ..]) => {
+                if access.is_empty() || !access.chars().all(|c| matches!(c, 'r' | 'w' | 'x')) {
+                    return Err(format!("Invalid access '{access}', expected any of r, w and x"));
+                }
+                let pause = match options {
+                    [] => true,
+                    ["log"] => false,
+                    _ => {
+                        return Err("Expected 'log' or nothing after the address".to_string());
+                    }
+                };
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (parse_addr(start)?, parse_addr(end)?),
+                    None => (parse_addr(range)?, parse_addr(range)?),
+                };
+                if end < start {
+                    return Err(format!("Invalid range '{range}'"));
+                }
+                emulator.add_watchpoint(Watchpoint {
+                    start,
+                    end,
+                    read: access.contains('r'),
+                    write: access.contains('w'),
+                    execute: access.contains('x'),
+                    pause,
+                });
+                println!("Watchpoint #{} set at {start:#05X}-{end:#05X}", emulator.watchpoints().len() - 1);
+            }
+            ("unwatch", [n]) => {
+                let n: usize = n.parse().map_err(|_| format!("Invalid watchpoint number '{n}'"))?;
+                if emulator.remove_watchpoint(n).is_none() {
+                    return Err(format!("No watchpoint #{n}"));
+                }
+                println!("Watchpoint #{n} deleted");
+            }
+            ("s" | "step", []) => self.step(emulator, observer, 1, drew)?,
+            ("s" | "step", [n]) => {
+                let n = n.parse().map_err(|_| format!("Invalid count '{n}'"))?;
+                self.step(emulator, observer, n, drew)?;
+            }
+            ("n" | "next", []) => {
+                let memory = emulator.memory();
+                let pc = emulator.pc();
+                match Instruction::decode_at(&memory[pc as usize..]) {
+                    Some(instruction @ Instruction::Call(_)) => {
+                        // run until the call returns to the same depth
+                        let return_addr = pc.wrapping_add(instruction.size() as u16);
+                        if emulator.breakpoints().all(|addr| addr != return_addr) {
+                            emulator.add_breakpoint(return_addr);
+                            self.step_over = Some((return_addr, emulator.stack().len()));
+                        }
+                        self.paused = false;
+                    }
+                    _ => self.step(emulator, observer, 1, drew)?,
+                }
+            }
+            ("c" | "continue", []) => {
+                self.paused = false;
+            }
+            ("pause", []) => {
+                if !self.paused {
+                    self.pause(emulator);
+                }
+            }
+            ("p" | "print", []) => {
+                print_state(emulator, &["regs", "stack"]);
+            }
+            ("p" | "print", what) => {
+                print_state(emulator, what);
+            }
+            ("l" | "list", []) => print_listing(emulator, emulator.pc(), 11),
+            ("l" | "list", [addr]) => print_listing(emulator, parse_addr(addr)?, 11),
+            ("l" | "list", [addr, n]) => {
+                let n = n.parse().map_err(|_| format!("Invalid count '{n}'"))?;
+                print_listing(emulator, parse_addr(addr)?, n);
+            }
+            ("x", [addr]) => hexdump(emulator.memory(), parse_addr(addr)?, 64),
+            ("x", [addr, len]) => {
+                let len = len.parse().map_err(|_| format!("Invalid length '{len}'"))?;
+                hexdump(emulator.memory(), parse_addr(addr)?, len);
+            }
+            ("h" | "help", []) => println!("{HELP}"),
+            ("q" | "quit", []) => poll.quit = true,
+            _ => {
+                return Err(format!("Unknown command '{}', type 'help' for a list of commands", line.trim()));
+            }
+        }
+        Ok(())
+    }
+
+    fn step(
+        &mut self,
+        emulator: &mut Chip8,
+        observer: &mut impl Observer,
+        n: usize,
+        drew: &mut bool
+    ) -> Result<(), String> {
+        for _ in 0..n {
+            let result = emulator.step_observed(observer).map_err(|why| format!("Failed: {why}"))?;
+            print_watch_hits(emulator);
+            *drew |= result.drew;
+            if result.exited {
+                println!("Program exited");
+                break;
+            }
+            if result.breakpoint && n > 1 {
+                println!("Breakpoint at {:#05X}", emulator.pc());
+                break;
+            }
+            if result.watchpoint {
+                break;
+            }
+        }
+        print_listing(emulator, emulator.pc(), 1);
+        Ok(())
+    }
+}
+
+fn print_watch_hits(emulator: &mut Chip8) {
+    for hit in emulator.take_watch_hits() {
+        println!("Watchpoint: {hit}");
+    }
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+/// Addresses are hex, with or without a `0x` or `$` prefix
+fn parse_addr(s: &str) -> Result<u16, String> {
+    let digits = s
+        .strip_prefix("0x")
+        .or(s.strip_prefix("0X"))
+        .or(s.strip_prefix('$'))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address '{s}'"))
+}
+
+fn print_state(emulator: &Chip8, what: &[&str]) {
+    for &what in what {
+        match what {
+            "regs" | "r" => {
+                for (row, registers) in emulator.registers().chunks(8).enumerate() {
+                    let registers: Vec<String> = registers
+                        .iter()
+                        .enumerate()
+                        .map(|(i, value)| format!("V{:X}={value:02X}", row * 8 + i))
+                        .collect();
+                    println!("{}", registers.join(" "));
+                }
+                println!("PC={:04X} I={:04X} SP={}", emulator.pc(), emulator.index(), emulator.stack().len());
+                println!("DT={:02X} ST={:02X}", emulator.delay_timer(), emulator.sound_timer());
+            }
+            "i" | "I" => println!("I={:04X}", emulator.index()),
+            "stack" => {
+                if emulator.stack().is_empty() {
+                    println!("Stack is empty");
+                }
+                for (depth, addr) in emulator.stack().iter().enumerate().rev() {
+                    println!("#{depth}  {addr:#05X}");
+                }
+            }
+            "timers" => println!("DT={:02X} ST={:02X}", emulator.delay_timer(), emulator.sound_timer()),
+            _ => println!("Can't print '{what}', expected regs, i, stack or timers"),
+        }
+    }
+}
+
+/// Disassembles `n` instructions centered on `addr`, marking the PC with `>`
+/// and breakpoints with `*`
+fn print_listing(emulator: &Chip8, addr: u16, n: usize) {
+    let memory = emulator.memory();
+    let n = n.min(memory.len());
+    // instructions are 2 bytes, bar the odd F000 NNNN
+    let mut i = (addr as usize).saturating_sub(2 * (n / 2));
+    for _ in 0..n {
+        if i >= memory.len() {
+            break;
+        }
+        let addr = i as u16;
+        let instruction = Instruction::decode_at(&memory[i..]);
+        let size = instruction.map_or(2, |instruction| instruction.size());
+        let line = DisasmLine {
+            addr,
+            bytes: memory[i..i.saturating_add(size).min(memory.len())].to_vec(),
+            instruction,
+        };
+        let pc_mark = if addr == emulator.pc() { '>' } else { ' ' };
+        let breakpoint_mark = if emulator.breakpoints().any(|breakpoint| breakpoint == addr) { '*' } else { ' ' };
+        println!("{pc_mark}{breakpoint_mark} {line}");
+        i = i.saturating_add(size);
+    }
+}
+
+/// Prints memory as rows of 16 hex bytes and their ASCII
+fn hexdump(memory: &[u8], addr: u16, len: usize) {
+    let start = addr as usize;
+    let end = start.saturating_add(len).min(memory.len());
+    for row_start in (start..end).step_by(16) {
+        let row = &memory[row_start..(row_start + 16).min(end)];
+        let hex: Vec<String> = row
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        let ascii: String = row
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        println!("{row_start:04X}  {:<47}  {ascii}", hex.join(" "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Sender;
+
+    use super::*;
+
+    /// Debugger fed through a channel rather than stdin, and an emulator
+    /// running `rom`
+    fn debugger(rom: &[u8]) -> (Sender<String>, Debugger, Chip8) {
+        let (command_tx, commands) = mpsc::channel();
+        let debugger = Debugger {
+            commands,
+            paused: true,
+            last_command: String::new(),
+            step_over: None,
+        };
+        let mut emulator = Chip8::new(700);
+        emulator.load_rom_bytes(rom).unwrap();
+        (command_tx, debugger, emulator)
+    }
+
+    /// Runs the emulator the way the main loop does, until the debugger pauses
+    fn run(debugger: &mut Debugger, emulator: &mut Chip8) {
+        for _ in 0..100 {
+            if debugger.paused() {
+                return;
+            }
+            let result = emulator.run_cycles(10);
+            debugger.frame_done(emulator, &result);
+        }
+        panic!("never paused");
+    }
+
+    /// ADD V0, 1, forever
+    const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn addresses_are_hex() {
+        for (s, addr) in [("200", 0x200), ("0x2a0", 0x2a0), ("0X1F", 0x1f), ("$FFFF", 0xffff)] {
+            assert_eq!(parse_addr(s), Ok(addr));
+        }
+        for s in ["", "0x", "zz", "10000", "-1"] {
+            assert_eq!(parse_addr(s), Err(format!("Invalid address '{s}'")));
+        }
+    }
+
+    #[test]
+    fn commands() {
+        let (_, mut debugger, mut emulator) = debugger(&COUNTER);
+        let mut poll = Poll::default();
+        let mut execute = |debugger: &mut Debugger, emulator: &mut Chip8, line| {
+            debugger.execute(emulator, &mut (), line, &mut poll)
+        };
+
+        execute(&mut debugger, &mut emulator, "b 0x300").unwrap();
+        execute(&mut debugger, &mut emulator, "break 202").unwrap();
+        assert_eq!(emulator.breakpoints().collect::<Vec<_>>(), [0x202, 0x300]);
+        execute(&mut debugger, &mut emulator, "d $300").unwrap();
+        assert_eq!(
+            execute(&mut debugger, &mut emulator, "delete 300"),
+            Err("No breakpoint at 0x300".to_string())
+        );
+
+        execute(&mut debugger, &mut emulator, "s 3").unwrap();
+        // stops at the breakpoint on the way
+        assert_eq!((emulator.pc(), emulator.registers()[0]), (0x202, 1));
+        execute(&mut debugger, &mut emulator, "step").unwrap();
+        assert_eq!(emulator.pc(), 0x200);
+        assert_eq!(execute(&mut debugger, &mut emulator, "s x"), Err("Invalid count 'x'".to_string()));
+        assert_eq!(execute(&mut debugger, &mut emulator, "x"), Err(
+            "Unknown command 'x', type 'help' for a list of commands".to_string()
+        ));
+        assert!(execute(&mut debugger, &mut emulator, "l 0x10000").is_err());
+        // running off the end of memory just stops listing
+        execute(&mut debugger, &mut emulator, "l ffff 100000").unwrap();
+        execute(&mut debugger, &mut emulator, "x fff0 99999999999").unwrap();
+        assert_eq!(
+            execute(&mut debugger, &mut emulator, "x 300 1f"),
+            Err("Invalid length '1f'".to_string())
+        );
+
+        execute(&mut debugger, &mut emulator, "c").unwrap();
+        assert!(!debugger.paused());
+        execute(&mut debugger, &mut emulator, "pause").unwrap();
+        assert!(debugger.paused());
+    }
+
+    #[test]
+    fn empty_lines_repeat_the_last_command() {
+        let (command_tx, mut debugger, mut emulator) = debugger(&COUNTER);
+        for line in ["s", "", " "] {
+            command_tx.send(line.to_string()).unwrap();
+        }
+        debugger.poll(&mut emulator, &mut ());
+        assert_eq!((emulator.pc(), emulator.registers()[0]), (0x202, 2));
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        // CALL 0x206, ADD V0, 1, JP 0x204, ADD V1, 1, RET
+        let rom = [0x22, 0x06, 0x70, 0x01, 0x12, 0x04, 0x71, 0x01, 0x00, 0xee];
+        let (command_tx, mut debugger, mut emulator) = debugger(&rom);
+        command_tx.send("n".to_string()).unwrap();
+        debugger.poll(&mut emulator, &mut ());
+        assert!(!debugger.paused());
+        run(&mut debugger, &mut emulator);
+        assert_eq!(emulator.pc(), 0x202);
+        assert_eq!(emulator.registers()[..2], [0, 1]);
+        // the temporary breakpoint is gone
+        assert_eq!(emulator.breakpoints().count(), 0);
+
+        // anything else is a single step
+        command_tx.send("".to_string()).unwrap();
+        debugger.poll(&mut emulator, &mut ());
+        assert!(debugger.paused());
+        assert_eq!(emulator.pc(), 0x204);
+    }
+
+    #[test]
+    fn next_steps_over_recursion() {
+        // CALL 0x204, JP 0x202, then a subroutine calling itself until V0 is 3:
+        // ADD V0, 1, SE V0, 3, CALL 0x204, RET
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x70, 0x01, 0x30, 0x03, 0x22, 0x04, 0x00, 0xee];
+        let (command_tx, mut debugger, mut emulator) = debugger(&rom);
+        command_tx.send("s 3".to_string()).unwrap();
+        debugger.poll(&mut emulator, &mut ());
+        assert_eq!((emulator.pc(), emulator.stack().len()), (0x208, 1));
+
+        // the inner calls return to 0x20A as well, but deeper
+        command_tx.send("next".to_string()).unwrap();
+        debugger.poll(&mut emulator, &mut ());
+        run(&mut debugger, &mut emulator);
+        assert_eq!((emulator.pc(), emulator.stack().len()), (0x20a, 1));
+        assert_eq!(emulator.registers()[0], 3);
+        assert_eq!(emulator.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn next_keeps_user_breakpoints() {
+        // CALL 0x204, JP 0x202, RET
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x00, 0xee];
+        let (command_tx, mut debugger, mut emulator) = debugger(&rom);
+        for line in ["b 202", "n"] {
+            command_tx.send(line.to_string()).unwrap();
+        }
+        debugger.poll(&mut emulator, &mut ());
+        run(&mut debugger, &mut emulator);
+        assert_eq!(emulator.pc(), 0x202);
+        assert_eq!(emulator.breakpoints().collect::<Vec<_>>(), [0x202]);
+    }
+
+    #[test]
+    fn next_cut_short_cleans_up() {
+        // CALL 0x204, JP 0x202, RET, with a breakpoint inside the subroutine
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x00, 0xee];
+        let (command_tx, mut debugger, mut emulator) = debugger(&rom);
+        for line in ["b 204", "n"] {
+            command_tx.send(line.to_string()).unwrap();
+        }
+        debugger.poll(&mut emulator, &mut ());
+        run(&mut debugger, &mut emulator);
+        assert_eq!(emulator.pc(), 0x204);
+        assert_eq!(emulator.breakpoints().collect::<Vec<_>>(), [0x204]);
+
+        // so continuing doesn't stop where the step over would have
+        command_tx.send("c".to_string()).unwrap();
+        debugger.poll(&mut emulator, &mut ());
+        let result = emulator.run_cycles(1);
+        debugger.frame_done(&mut emulator, &result);
+        assert!(!debugger.paused());
+    }
+
+    #[test]
+    fn quitting() {
+        let (command_tx, mut quitter, mut emulator) = debugger(&COUNTER);
+        for line in ["s", "q", "s"] {
+            command_tx.send(line.to_string()).unwrap();
+        }
+        assert!(quitter.poll(&mut emulator, &mut ()).quit);
+        // nothing after the quit runs
+        assert_eq!(emulator.pc(), 0x202);
+
+        // and neither can anything once stdin's closed
+        let (command_tx, mut debugger, mut emulator) = debugger(&COUNTER);
+        drop(command_tx);
+        assert!(debugger.poll(&mut emulator, &mut ()).quit);
+    }
+
+    #[test]
+    fn watch_commands() {
+        let (_, mut debugger, mut emulator) = debugger(&COUNTER);
+        let mut poll = Poll::default();
+        let mut execute = |line| debugger.execute(&mut emulator, &mut (), line, &mut poll);
+        execute("w rw 300-30f").unwrap();
+        execute("watch x 0x202 log").unwrap();
+        for (line, error) in [
+            ("w q 300", "Invalid access 'q', expected any of r, w and x"),
+            ("w r 300 now", "Expected 'log' or nothing after the address"),
+            ("w r 30f-300", "Invalid range '30f-300'"),
+            ("w r zz", "Invalid address 'zz'"),
+            ("unwatch 2", "No watchpoint #2"),
+            ("unwatch one", "Invalid watchpoint number 'one'"),
+        ] {
+            assert_eq!(execute(line), Err(error.to_string()), "{line}");
+        }
+        execute("unwatch 0").unwrap();
+
+        let watchpoint = Watchpoint {
+            start: 0x202,
+            end: 0x202,
+            read: false,
+            write: false,
+            execute: true,
+            pause: false,
+        };
+        assert_eq!(emulator.watchpoints(), [watchpoint]);
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+
+use crate::{ Instruction, PROGRAM_START_ADDR };
+
+/// One line of a disassembly listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    /// Address of the first byte
+    pub addr: u16,
+    /// Raw bytes covered by this line (2-4 for instructions, 1-2 for data)
+    pub bytes: Vec<u8>,
+    /// Decoded instruction, `None` for data
+    pub instruction: Option<Instruction>,
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let raw: String = self.bytes
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect();
+        write!(f, "0x{:03X}  {:<4}  ", self.addr, raw)?;
+        match self.instruction {
+            Some(instruction) => write!(f, "{instruction}"),
+            None => {
+                let data: Vec<String> = self.bytes
+                    .iter()
+                    .map(|b| format!("0x{b:02X}"))
+                    .collect();
+                write!(f, "db {}", data.join(", "))
+            }
+        }
+    }
+}
+
+/// Disassembles a ROM, telling code apart from data
+///
+/// Starting at the program start address, every instruction reachable through
+/// jumps, calls and skips is marked as code, and anything that was never
+/// reached is listed as data. Computed jumps (BNNN) can't be followed, so any
+/// jump table found at their base address is swept linearly instead. If no
+/// code is reachable at all, this falls back to [`disassemble_linear`].
+pub fn disassemble(rom: &[u8]) -> Vec<DisasmLine> {
+    let is_code = find_reachable(rom);
+    if !is_code.contains(&true) {
+        return disassemble_linear(rom);
+    }
+
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < rom.len() {
+        let addr = PROGRAM_START_ADDR + (i as u16);
+        let instruction = if is_code[i] { Instruction::decode_at(&rom[i..]) } else { None };
+        if let Some(instruction) = instruction {
+            lines.push(DisasmLine {
+                addr,
+                bytes: rom[i..i + instruction.size()].to_vec(),
+                instruction: Some(instruction),
+            });
+            i += instruction.size();
+        } else {
+            // data, grouped in words unless code starts on the next byte
+            let len = if i + 1 < rom.len() && !is_code[i + 1] { 2 } else { 1 };
+            lines.push(DisasmLine {
+                addr,
+                bytes: rom[i..i + len].to_vec(),
+                instruction: None,
+            });
+            i += len;
+        }
+    }
+    lines
+}
+
+/// Disassembles every word of a ROM as an instruction, without any analysis
+pub fn disassemble_linear(rom: &[u8]) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < rom.len() {
+        let instruction = Instruction::decode_at(&rom[i..]);
+        let len = match instruction {
+            Some(instruction) => instruction.size(),
+            None => (rom.len() - i).min(2),
+        };
+        lines.push(DisasmLine {
+            addr: PROGRAM_START_ADDR + (i as u16),
+            bytes: rom[i..i + len].to_vec(),
+            instruction,
+        });
+        i += len;
+    }
+    lines
+}
+
+/// Walks the control flow from the program start address, returning which
+/// ROM bytes start an instruction
+fn find_reachable(rom: &[u8]) -> Vec<bool> {
+    let mut is_code = vec![false; rom.len()];
+    let mut visited = vec![false; rom.len()];
+    let mut to_visit = vec![PROGRAM_START_ADDR];
+
+    // converting an address to a ROM offset, if it lands in the ROM
+    let offset = |addr: u16| -> Option<usize> {
+        let i = addr.checked_sub(PROGRAM_START_ADDR)? as usize;
+        if i + 1 < rom.len() { Some(i) } else { None }
+    };
+
+    while let Some(addr) = to_visit.pop() {
+        let i = match offset(addr) {
+            Some(i) if !visited[i] => i,
+            _ => {
+                continue;
+            }
+        };
+        visited[i] = true;
+
+        let instruction = match Instruction::decode_at(&rom[i..]) {
+            // machine code routines are never going to run here, so it's data
+            Some(Instruction::Sys(_)) | None => {
+                continue;
+            }
+            Some(instruction) => instruction,
+        };
+        is_code[i] = true;
+
+        let next = addr.wrapping_add(instruction.size() as u16);
+        match instruction {
+            Instruction::Ret | Instruction::Exit => {}
+            Instruction::Jp(nnn) => to_visit.push(nnn),
+            Instruction::Call(nnn) => {
+                to_visit.push(nnn);
+                to_visit.push(next);
+            }
+            Instruction::JpV0(nnn) => {
+                // jump tables are almost always a run of JP instructions
+                let mut entry = nnn;
+                while let Some(j) = offset(entry) {
+                    let opcode = u16::from_be_bytes([rom[j], rom[j + 1]]);
+                    match Instruction::decode(opcode) {
+                        Some(Instruction::Jp(_)) => to_visit.push(entry),
+                        _ => {
+                            break;
+                        }
+                    }
+                    entry = entry.wrapping_add(2);
+                }
+                // the first entry is reachable with V0 == 0, whatever it is
+                to_visit.push(nnn);
+            }
+            Instruction::SeImm(..) |
+            Instruction::SneImm(..) |
+            Instruction::SeReg(..) |
+            Instruction::SneReg(..) |
+            Instruction::Skp(_) |
+            Instruction::Sknp(_) => {
+                // skipping over F000 NNNN skips both words
+                let next_size = match offset(next) {
+                    Some(j) if rom[j] == 0xf0 && rom[j + 1] == 0x00 => 4,
+                    _ => 2,
+                };
+                to_visit.push(next);
+                to_visit.push(next.wrapping_add(next_size));
+            }
+            _ => to_visit.push(next),
+        }
+    }
+    is_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Addresses of the lines that were decoded as code
+    fn code(lines: &[DisasmLine]) -> Vec<u16> {
+        lines
+            .iter()
+            .filter(|line| line.instruction.is_some())
+            .map(|line| line.addr)
+            .collect()
+    }
+
+    #[test]
+    fn jumps_step_over_data() {
+        // JP 0x206, then a sprite, CLS, JP 0x208
+        let rom = [0x12, 0x06, 0x00, 0xe0, 0x3c, 0x42, 0x00, 0xe0, 0x12, 0x08];
+        let lines = disassemble(&rom);
+        assert_eq!(code(&lines), [0x200, 0x206, 0x208]);
+        // even though the sprite's first word decodes as CLS
+        assert_eq!(lines[1], DisasmLine { addr: 0x202, bytes: vec![0x00, 0xe0], instruction: None });
+        assert_eq!(lines[1].to_string(), "0x202  00E0  db 0x00, 0xE0");
+        assert_eq!(lines[3].to_string(), "0x206  00E0  CLS");
+    }
+
+    #[test]
+    fn calls_and_skips_continue_on_both_paths() {
+        // CALL 0x208, SE V0, 1, JP 0x204, JP 0x206, RET, data
+        let rom = [0x22, 0x08, 0x30, 0x01, 0x12, 0x04, 0x12, 0x06, 0x00, 0xee, 0xff, 0xff];
+        let lines = disassemble(&rom);
+        assert_eq!(code(&lines), [0x200, 0x202, 0x204, 0x206, 0x208]);
+        assert_eq!(lines.last().unwrap().addr, 0x20a);
+    }
+
+    #[test]
+    fn jump_tables_are_swept() {
+        // JP V0, 0x204, data, a table of two jumps, data, the jumps' targets
+        let rom = [
+            0xb2, 0x04, 0xaa, 0xbb, 0x12, 0x0a, 0x12, 0x0c, 0xff, 0xff, 0x12, 0x0a, 0x12, 0x0c,
+        ];
+        assert_eq!(code(&disassemble(&rom)), [0x200, 0x204, 0x206, 0x20a, 0x20c]);
+    }
+
+    #[test]
+    fn unreachable_code_falls_back_to_linear() {
+        let rom = [0xff, 0xff, 0x00, 0xe0, 0x12];
+        let lines = disassemble(&rom);
+        assert_eq!(lines, disassemble_linear(&rom));
+        assert_eq!(code(&lines), [0x202]);
+        // the odd byte at the end
+        assert_eq!(lines[2].to_string(), "0x204  12    db 0x12");
+    }
+}
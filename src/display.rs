@@ -1,17 +1,34 @@
 #![allow(dead_code)]
-use std::sync::{ Arc, Mutex };
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex, mpsc::Sender };
 use winit::{
     dpi::LogicalSize,
-    event::{ ElementState, Event, KeyEvent, WindowEvent },
-    event_loop::EventLoop,
+    event::{ ElementState, Event, KeyEvent, Modifiers, WindowEvent },
+    event_loop::{ EventLoop, EventLoopProxy },
     keyboard::{ KeyCode, PhysicalKey },
     window::{ Window, WindowBuilder },
 };
-use pixels::{ Pixels, SurfaceTexture };
+use pixels::{ Pixels, SurfaceTexture, wgpu::Color };
 
 use crate::*;
 
-pub const PIXEL_SIZE: usize = 15;
+/// Window pixels per high resolution pixel
+pub const PIXEL_SIZE: usize = 8;
+
+/// Emulator controls that aren't part of the CHIP-8 keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    /// Shift+F1-F9
+    SaveState(u8),
+    /// F1-F9
+    LoadState(u8),
+    /// Backspace pressed (true) or released (false), steps back in time while held
+    Rewind(bool),
+    /// F12
+    Screenshot,
+    /// F10, starts or stops recording a GIF/video
+    ToggleCapture,
+}
 
 pub struct Display {
     size: LogicalSize<u32>,
@@ -23,7 +40,10 @@ impl Display {
     pub fn create_window() -> Self {
         println_debug!("Configuring window");
         env_logger::init();
-        let size = LogicalSize::new((SCREEN_W * PIXEL_SIZE) as u32, (SCREEN_H * PIXEL_SIZE) as u32);
+        let size = LogicalSize::new(
+            (HIRES_SCREEN_W * PIXEL_SIZE) as u32,
+            (HIRES_SCREEN_H * PIXEL_SIZE) as u32
+        );
         println_debug!(" - Size: {} x {}", size.width, size.height);
         let event_loop = EventLoop::new().unwrap();
         let window = WindowBuilder::new()
@@ -42,87 +62,104 @@ impl Display {
 
     pub fn create_pixel_buf(&self) -> Pixels {
         let surface_texture = SurfaceTexture::new(self.size.width, self.size.height, &self.window);
-        let pixels = Pixels::new(SCREEN_W as u32, SCREEN_H as u32, surface_texture).unwrap();
-        pixels
+        Pixels::new(HIRES_SCREEN_W as u32, HIRES_SCREEN_H as u32, surface_texture).unwrap()
+    }
+
+    /// Creates the frontend that draws the emulator's framebuffer into this window
+    pub fn create_screen(&self, palette: Palette) -> Screen {
+        Screen::new(self.create_pixel_buf(), palette)
     }
 
-    pub fn run_event_loop(self, keypad_state: Arc<Mutex<[bool; 16]>>) -> () {
-        let update_keypad = |key: usize, pressed: bool| {
+    /// Creates a handle that closes the window from another thread, by
+    /// sending it an event
+    pub fn create_closer(&self) -> EventLoopProxy<()> {
+        self.event_loop.create_proxy()
+    }
+
+    pub fn run_event_loop(
+        self,
+        keymap: Keymap,
+        keypad_state: Arc<Mutex<[bool; 16]>>,
+        hotkeys: Sender<Hotkey>
+    ) {
+        // keyboard keys holding down CHIP-8 keys, by the key they press so a
+        // release finds it even if the layout or modifiers changed meanwhile
+        let mut held: HashMap<PhysicalKey, usize> = HashMap::new();
+        let update_keypad = |held: &HashMap<PhysicalKey, usize>, key: usize| {
             let mut keypad_state = keypad_state.lock().unwrap();
-            (*keypad_state)[key] = pressed;
+            (*keypad_state)[key] = held.values().any(|held_key| *held_key == key);
         };
+        let send_hotkey = |hotkey: Hotkey| {
+            // the emulator thread is gone once the program stops
+            let _ = hotkeys.send(hotkey);
+        };
+        let mut modifiers = Modifiers::default();
 
         println_debug!("Starting window event loop");
         self.event_loop
             .run(|event, window_target| {
-                match event {
-                    Event::WindowEvent { window_id: _, event } => {
-                        match event {
-                            WindowEvent::CloseRequested => {
-                                window_target.exit();
-                            }
-                            WindowEvent::KeyboardInput {
-                                event: KeyEvent {
-                                    physical_key,
-                                    state: ElementState::Pressed,
-                                    repeat: false,
-                                    ..
-                                },
+                // the only event sent from elsewhere, see `create_closer`
+                if let Event::UserEvent(()) = event {
+                    window_target.exit();
+                    return;
+                }
+                if let Event::WindowEvent { window_id: _, event } = event {
+                    match event {
+                        WindowEvent::CloseRequested => {
+                            window_target.exit();
+                        }
+                        WindowEvent::ModifiersChanged(new_modifiers) => {
+                            modifiers = new_modifiers;
+                        }
+                        WindowEvent::KeyboardInput {
+                            event: event @ KeyEvent {
+                                state: ElementState::Pressed,
+                                repeat: false,
                                 ..
-                            } => {
-                                match physical_key {
-                                    PhysicalKey::Code(KeyCode::Digit1) => update_keypad(0x1, true),
-                                    PhysicalKey::Code(KeyCode::Digit2) => update_keypad(0x2, true),
-                                    PhysicalKey::Code(KeyCode::Digit3) => update_keypad(0x3, true),
-                                    PhysicalKey::Code(KeyCode::Digit4) => update_keypad(0xc, true),
-                                    PhysicalKey::Code(KeyCode::KeyQ) => update_keypad(0x4, true),
-                                    PhysicalKey::Code(KeyCode::KeyW) => update_keypad(0x5, true),
-                                    PhysicalKey::Code(KeyCode::KeyE) => update_keypad(0x6, true),
-                                    PhysicalKey::Code(KeyCode::KeyR) => update_keypad(0xd, true),
-                                    PhysicalKey::Code(KeyCode::KeyA) => update_keypad(0x7, true),
-                                    PhysicalKey::Code(KeyCode::KeyS) => update_keypad(0x8, true),
-                                    PhysicalKey::Code(KeyCode::KeyD) => update_keypad(0x9, true),
-                                    PhysicalKey::Code(KeyCode::KeyF) => update_keypad(0xe, true),
-                                    PhysicalKey::Code(KeyCode::KeyZ) => update_keypad(0xa, true),
-                                    PhysicalKey::Code(KeyCode::KeyX) => update_keypad(0x0, true),
-                                    PhysicalKey::Code(KeyCode::KeyC) => update_keypad(0xb, true),
-                                    PhysicalKey::Code(KeyCode::KeyV) => update_keypad(0xf, true),
-                                    _ => {}
+                            },
+                            ..
+                        } => {
+                            if let Some(key) = keymap.key(&event) {
+                                held.insert(event.physical_key, key);
+                                update_keypad(&held, key);
+                                return;
+                            }
+                            match event.physical_key {
+                                PhysicalKey::Code(KeyCode::Backspace) => send_hotkey(Hotkey::Rewind(true)),
+                                PhysicalKey::Code(KeyCode::F12) => send_hotkey(Hotkey::Screenshot),
+                                PhysicalKey::Code(KeyCode::F10) => send_hotkey(Hotkey::ToggleCapture),
+                                PhysicalKey::Code(code) => {
+                                    if let Some(slot) = state_slot(code) {
+                                        if modifiers.state().shift_key() {
+                                            send_hotkey(Hotkey::SaveState(slot));
+                                        } else {
+                                            send_hotkey(Hotkey::LoadState(slot));
+                                        }
+                                    }
                                 }
+                                _ => {}
                             }
-                            WindowEvent::KeyboardInput {
-                                event: KeyEvent {
-                                    physical_key,
-                                    state: ElementState::Released,
-                                    repeat: false,
-                                    ..
-                                },
+                        }
+                        WindowEvent::KeyboardInput {
+                            event: KeyEvent {
+                                physical_key,
+                                state: ElementState::Released,
+                                repeat: false,
                                 ..
-                            } => {
-                                match physical_key {
-                                    PhysicalKey::Code(KeyCode::Digit1) => update_keypad(0x1, false),
-                                    PhysicalKey::Code(KeyCode::Digit2) => update_keypad(0x2, false),
-                                    PhysicalKey::Code(KeyCode::Digit3) => update_keypad(0x3, false),
-                                    PhysicalKey::Code(KeyCode::Digit4) => update_keypad(0xc, false),
-                                    PhysicalKey::Code(KeyCode::KeyQ) => update_keypad(0x4, false),
-                                    PhysicalKey::Code(KeyCode::KeyW) => update_keypad(0x5, false),
-                                    PhysicalKey::Code(KeyCode::KeyE) => update_keypad(0x6, false),
-                                    PhysicalKey::Code(KeyCode::KeyR) => update_keypad(0xd, false),
-                                    PhysicalKey::Code(KeyCode::KeyA) => update_keypad(0x7, false),
-                                    PhysicalKey::Code(KeyCode::KeyS) => update_keypad(0x8, false),
-                                    PhysicalKey::Code(KeyCode::KeyD) => update_keypad(0x9, false),
-                                    PhysicalKey::Code(KeyCode::KeyF) => update_keypad(0xe, false),
-                                    PhysicalKey::Code(KeyCode::KeyZ) => update_keypad(0xa, false),
-                                    PhysicalKey::Code(KeyCode::KeyX) => update_keypad(0x0, false),
-                                    PhysicalKey::Code(KeyCode::KeyC) => update_keypad(0xb, false),
-                                    PhysicalKey::Code(KeyCode::KeyV) => update_keypad(0xf, false),
-                                    _ => {}
-                                }
+                            },
+                            ..
+                        } => {
+                            if let Some(key) = held.remove(&physical_key) {
+                                update_keypad(&held, key);
+                                return;
+                            }
+                            if physical_key == PhysicalKey::Code(KeyCode::Backspace) {
+                                send_hotkey(Hotkey::Rewind(false));
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
-                    _ => {}
+
                 }
             })
             .unwrap();
@@ -133,3 +170,52 @@ impl Display {
         self.window.set_title(new_title.as_str())
     }
 }
+
+/// Save state slot of a function key (F1 is slot 1)
+fn state_slot(code: KeyCode) -> Option<u8> {
+    let slot = match code {
+        KeyCode::F1 => 1,
+        KeyCode::F2 => 2,
+        KeyCode::F3 => 3,
+        KeyCode::F4 => 4,
+        KeyCode::F5 => 5,
+        KeyCode::F6 => 6,
+        KeyCode::F7 => 7,
+        KeyCode::F8 => 8,
+        KeyCode::F9 => 9,
+        _ => {
+            return None;
+        }
+    };
+    Some(slot)
+}
+
+/// Frontend that turns the emulator's framebuffer into pixels on the window
+pub struct Screen {
+    pixels: Pixels,
+    palette: Palette,
+}
+
+impl Screen {
+    pub fn new(mut pixels: Pixels, palette: Palette) -> Self {
+        // the border around the screen when the window is scaled
+        let [r, g, b, a] = palette[0].map(|channel| channel as f64 / 255.0);
+        pixels.clear_color(Color { r, g, b, a });
+        Self { pixels, palette }
+    }
+}
+
+impl Frontend for Screen {
+    fn render(&mut self, pixel_buf: &PixelBuf, hires: bool) {
+        // low resolution pixels are drawn 2x2
+        let scale = if hires { 1 } else { 2 };
+        for (i, pixel) in self.pixels.frame_mut().chunks_exact_mut(4).enumerate() {
+            let x = (i % HIRES_SCREEN_W) / scale;
+            let y = (i / HIRES_SCREEN_W) / scale;
+            let rgba = self.palette[(pixel_buf[y][x] & 0x3) as usize];
+
+            pixel.copy_from_slice(&rgba);
+        }
+        self.pixels.render().unwrap();
+    }
+}
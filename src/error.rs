@@ -0,0 +1,102 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while loading or executing a program
+///
+/// Execution errors carry the address of the faulting instruction (`pc`) and
+/// the raw instruction word (`opcode`).
+#[derive(Debug)]
+pub enum Chip8Error {
+    /// Instruction doesn't decode to anything this interpreter supports
+    UnknownOpcode { pc: u16, opcode: u16 },
+    /// 00EE executed with nothing on the stack
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// 2NNN executed with a full stack
+    StackOverflow { pc: u16, opcode: u16 },
+    /// Instruction accessed memory past the end of the address space
+    MemoryOutOfBounds { pc: u16, opcode: u16, addr: usize },
+    /// Sprite drawn (partially) outside of the screen
+    ///
+    /// No longer returned: sprites running off the screen are clipped, or
+    /// wrapped around with [`Quirks::wrap_sprites`](crate::Quirks::wrap_sprites),
+    /// as on the real interpreters.
+    #[deprecated(note = "sprites are clipped or wrapped (Quirks::wrap_sprites) instead of failing")]
+    DrawOutOfBounds { pc: u16, opcode: u16, x: usize, y: usize },
+    /// ROM doesn't fit in memory after the program start address
+    RomTooLarge { size: usize, max: usize },
+    /// Save state is corrupt, truncated or from an incompatible version
+    InvalidState(String),
+    /// Failed to read or write a file
+    Io(io::Error),
+}
+
+impl Chip8Error {
+    /// Address of the faulting instruction, if the error happened during execution
+    #[allow(deprecated)]
+    pub fn pc(&self) -> Option<u16> {
+        match self {
+            Chip8Error::UnknownOpcode { pc, .. }
+            | Chip8Error::StackUnderflow { pc, .. }
+            | Chip8Error::StackOverflow { pc, .. }
+            | Chip8Error::MemoryOutOfBounds { pc, .. }
+            | Chip8Error::DrawOutOfBounds { pc, .. } => Some(*pc),
+            Chip8Error::RomTooLarge { .. } | Chip8Error::InvalidState(_) | Chip8Error::Io(_) => None,
+        }
+    }
+
+    /// Faulting instruction word, if the error happened during execution
+    #[allow(deprecated)]
+    pub fn opcode(&self) -> Option<u16> {
+        match self {
+            Chip8Error::UnknownOpcode { opcode, .. }
+            | Chip8Error::StackUnderflow { opcode, .. }
+            | Chip8Error::StackOverflow { opcode, .. }
+            | Chip8Error::MemoryOutOfBounds { opcode, .. }
+            | Chip8Error::DrawOutOfBounds { opcode, .. } => Some(*opcode),
+            Chip8Error::RomTooLarge { .. } | Chip8Error::InvalidState(_) | Chip8Error::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown instruction {opcode:#06X} at {pc:#05X}")
+            }
+            Chip8Error::StackUnderflow { pc, opcode } => {
+                write!(f, "Returned outside of subroutine ({opcode:#06X} at {pc:#05X})")
+            }
+            Chip8Error::StackOverflow { pc, opcode } => {
+                write!(f, "Stack overflow ({opcode:#06X} at {pc:#05X})")
+            }
+            Chip8Error::MemoryOutOfBounds { pc, opcode, addr } => {
+                write!(f, "Memory access out of bounds at {addr:#X} ({opcode:#06X} at {pc:#05X})")
+            }
+            Chip8Error::DrawOutOfBounds { pc, opcode, x, y } => {
+                write!(f, "Drew outside of the screen at ({x}, {y}) ({opcode:#06X} at {pc:#05X})")
+            }
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is too large ({size} bytes, max is {max})")
+            }
+            Chip8Error::InvalidState(why) => write!(f, "Invalid save state: {why}"),
+            Chip8Error::Io(why) => write!(f, "{why}"),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(why: io::Error) -> Self {
+        Chip8Error::Io(why)
+    }
+}
@@ -0,0 +1,753 @@
+use std::collections::BTreeSet;
+use std::io::{ self, BufReader, Read, Write };
+use std::net::{ Ipv4Addr, TcpListener, TcpStream };
+use std::sync::mpsc::{ self, Receiver, Sender, TryRecvError };
+use std::thread;
+
+use crate::*;
+
+/// Register numbers past V0-VF, in the order of the `g` packet
+const REG_I: usize = N_REGISTERS;
+const REG_PC: usize = N_REGISTERS + 1;
+const REG_SP: usize = N_REGISTERS + 2;
+
+/// Size of the registers, in the order of the `g` packet
+fn register_size(n: usize) -> Option<usize> {
+    match n {
+        0..=15 | REG_SP => Some(1),
+        REG_I | REG_PC => Some(2),
+        _ => None,
+    }
+}
+
+/// Stop reasons, as the signal numbers GDB expects
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// What the connection thread passes on to the emulator thread
+enum Event {
+    Connected(TcpStream),
+    Packet(String),
+    /// Ctrl-C from the client
+    Interrupt,
+    Disconnected,
+}
+
+/// GDB remote serial protocol stub, so gdb or lldb can drive the emulator
+///
+/// Connections are accepted and read on a separate thread, and their packets
+/// handled by the emulator thread whenever it polls for them. Execution is
+/// paused until a client connects, and resumes when it detaches.
+///
+/// The registers are V0-VF (8 bit), I and PC (16 bit, little endian), and SP
+/// (8 bit, the number of return addresses on the stack), described to the
+/// client through `target.xml`.
+pub struct GdbStub {
+    events: Receiver<Event>,
+    client: Option<TcpStream>,
+    paused: bool,
+    /// Breakpoints and watchpoints set by the client, removed again when it
+    /// goes away
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl GdbStub {
+    /// Starts listening for a client on a local port
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let (event_tx, events) = mpsc::channel();
+        thread::Builder::new()
+            .name("gdb_connection".to_string())
+            .spawn(move || {
+                // one client at a time
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(why) => {
+                            println!("Failed to accept GDB connection: {why}");
+                            continue;
+                        }
+                    };
+                    if read_packets(stream, &event_tx).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        println!("Waiting for GDB to connect on {}:{port}", Ipv4Addr::LOCALHOST);
+        Ok(Self {
+            events,
+            client: None,
+            paused: true,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+        })
+    }
+
+    /// Whether execution should be held, checked before every frame
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Handles any packets that have come in since the last call
+    ///
+    /// Returns whether the screen might have changed and whether the client
+    /// killed the program. Stepped instructions are shown to `observer`.
+    pub fn poll(&mut self, emulator: &mut Chip8, observer: &mut impl Observer) -> Poll {
+        let mut poll = Poll::default();
+        loop {
+            let event = match self.events.try_recv() {
+                Ok(event) => event,
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {
+                    return poll;
+                }
+            };
+            match event {
+                Event::Connected(stream) => {
+                    if let Ok(addr) = stream.peer_addr() {
+                        println!("GDB connected from {addr}");
+                    }
+                    self.client = Some(stream);
+                    self.paused = true;
+                }
+                Event::Packet(packet) => {
+                    if let Some(reply) = self.handle_packet(emulator, observer, &packet, &mut poll) {
+                        self.send(&reply);
+                    }
+                }
+                Event::Interrupt => {
+                    self.paused = true;
+                    self.send(&format!("S{SIGINT:02x}"));
+                }
+                Event::Disconnected => {
+                    println!("GDB disconnected");
+                    self.detach(emulator);
+                }
+            }
+        }
+    }
+
+    /// Reacts to the result of a frame, stopping at breakpoints, errors and
+    /// the end of the program
+    pub fn frame_done(&mut self, emulator: &mut Chip8, result: &Result<StepResult, Chip8Error>) {
+        if self.client.is_none() {
+            return;
+        }
+        let reply = match result {
+            Ok(result) if result.exited => "W00".to_string(),
+            Ok(result) if result.breakpoint || result.watchpoint => self.stop_reply(emulator),
+            Ok(_) => {
+                // only pausing watchpoints are set, so nothing to report
+                emulator.take_watch_hits();
+                return;
+            }
+            Err(_) => format!("S{SIGILL:02x}"),
+        };
+        self.paused = true;
+        self.send(&reply);
+    }
+
+    /// Stop reply after a trap, naming the watched address if a watchpoint
+    /// was hit
+    fn stop_reply(&self, emulator: &mut Chip8) -> String {
+        let Some(hit) = emulator.take_watch_hits().into_iter().next() else {
+            return format!("S{SIGTRAP:02x}");
+        };
+        let access_watched = self.watchpoints
+            .iter()
+            .any(|w| w.read && w.write && (w.start..=w.end).contains(&hit.addr));
+        let reason = match hit.access {
+            _ if access_watched => "awatch",
+            Access::Write => "watch",
+            _ => "rwatch",
+        };
+        format!("T{SIGTRAP:02x}{reason}:{:x};", hit.addr)
+    }
+
+    fn detach(&mut self, emulator: &mut Chip8) {
+        for addr in std::mem::take(&mut self.breakpoints) {
+            emulator.remove_breakpoint(addr);
+        }
+        for watchpoint in std::mem::take(&mut self.watchpoints) {
+            if let Some(i) = emulator.watchpoints().iter().position(|w| *w == watchpoint) {
+                emulator.remove_watchpoint(i);
+            }
+        }
+        self.client = None;
+        self.paused = false;
+    }
+
+    /// Replies to a packet, `None` when the reply is a stop reply sent later
+    fn handle_packet(
+        &mut self,
+        emulator: &mut Chip8,
+        observer: &mut impl Observer,
+        packet: &str,
+        poll: &mut Poll
+    ) -> Option<String> {
+        let error = "E01".to_string();
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{SIGTRAP:02x}"),
+            "g" => {
+                (0..=REG_SP)
+                    .map(|n| encode_hex(&read_register(emulator, n)))
+                    .collect()
+            }
+            "G" => {
+                let Some(bytes) = decode_hex(args) else {
+                    return Some(error);
+                };
+                let mut bytes = bytes.as_slice();
+                for n in 0..=REG_SP {
+                    let size = register_size(n).unwrap_or_default();
+                    if bytes.len() < size {
+                        break;
+                    }
+                    write_register(emulator, n, &bytes[..size]);
+                    bytes = &bytes[size..];
+                }
+                "OK".to_string()
+            }
+            "p" => {
+                match usize::from_str_radix(args, 16).ok().filter(|&n| n <= REG_SP) {
+                    Some(n) => encode_hex(&read_register(emulator, n)),
+                    None => error,
+                }
+            }
+            "P" => {
+                let register = args.split_once('=').and_then(|(n, value)| {
+                    let n = usize::from_str_radix(n, 16).ok().filter(|&n| n <= REG_SP)?;
+                    Some((n, decode_hex(value)?))
+                });
+                match register {
+                    Some((n, value)) if value.len() == register_size(n).unwrap_or_default() => {
+                        write_register(emulator, n, &value);
+                        "OK".to_string()
+                    }
+                    _ => error,
+                }
+            }
+            "m" => {
+                let range = args.split_once(',').and_then(|(addr, len)| {
+                    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+                });
+                match range {
+                    Some((addr, len)) if addr < MEM_SIZE => {
+                        let memory = emulator.memory();
+                        encode_hex(&memory[addr..addr.saturating_add(len).min(memory.len())])
+                    }
+                    _ => error,
+                }
+            }
+            "M" => {
+                let write = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = range.split_once(',')?;
+                    let addr = usize::from_str_radix(addr, 16).ok().filter(|&addr| addr < MEM_SIZE)?;
+                    let data = decode_hex(data)?;
+                    let len = usize::from_str_radix(len, 16).ok().filter(|&len| len == data.len())?;
+                    (addr.saturating_add(len) <= MEM_SIZE).then_some((addr as u16, data))
+                });
+                match write {
+                    Some((addr, data)) => {
+                        emulator.write_memory(addr, &data);
+                        "OK".to_string()
+                    }
+                    None => error,
+                }
+            }
+            // software and hardware breakpoints work the same
+            "Z" | "z" => {
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let addr = fields.next().and_then(|addr| u16::from_str_radix(addr, 16).ok());
+                match (kind, addr) {
+                    (Some("0" | "1"), Some(addr)) if command == "Z" => {
+                        if !emulator.breakpoints().any(|breakpoint| breakpoint == addr) {
+                            emulator.add_breakpoint(addr);
+                            self.breakpoints.insert(addr);
+                        }
+                        "OK".to_string()
+                    }
+                    (Some("0" | "1"), Some(addr)) => {
+                        if self.breakpoints.remove(&addr) {
+                            emulator.remove_breakpoint(addr);
+                        }
+                        "OK".to_string()
+                    }
+                    // write, read and access watchpoints
+                    (Some(kind @ ("2" | "3" | "4")), Some(addr)) => {
+                        let len = fields.next().and_then(|len| u16::from_str_radix(len, 16).ok());
+                        let Some(len) = len.filter(|&len| len > 0) else {
+                            return Some(error);
+                        };
+                        let watchpoint = Watchpoint {
+                            start: addr,
+                            end: addr.saturating_add(len - 1),
+                            read: kind != "2",
+                            write: kind != "3",
+                            execute: false,
+                            pause: true,
+                        };
+                        if command == "Z" {
+                            emulator.add_watchpoint(watchpoint.clone());
+                            self.watchpoints.push(watchpoint);
+                        } else if let Some(i) = self.watchpoints.iter().position(|w| *w == watchpoint) {
+                            self.watchpoints.remove(i);
+                            if let Some(i) = emulator.watchpoints().iter().position(|w| *w == watchpoint) {
+                                emulator.remove_watchpoint(i);
+                            }
+                        }
+                        "OK".to_string()
+                    }
+                    (Some(_), Some(_)) => String::new(),
+                    _ => error,
+                }
+            }
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(addr) => emulator.set_pc(addr),
+                        Err(_) => {
+                            return Some(error);
+                        }
+                    }
+                }
+                if command == "c" {
+                    self.paused = false;
+                    return None;
+                }
+                match emulator.step_observed(observer) {
+                    Ok(result) => {
+                        poll.drew |= result.drew;
+                        if result.exited { "W00".to_string() } else { self.stop_reply(emulator) }
+                    }
+                    Err(_) => format!("S{SIGILL:02x}"),
+                }
+            }
+            "D" => {
+                self.send("OK");
+                println!("GDB detached");
+                self.detach(emulator);
+                return None;
+            }
+            "k" => {
+                // no reply, the program shuts down as if the window was closed
+                println!("GDB killed the program");
+                poll.quit = true;
+                return None;
+            }
+            // one thread, which is always alive
+            "H" | "T" => "OK".to_string(),
+            _ => {
+                match packet {
+                    _ if packet.starts_with("qSupported") => {
+                        "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string()
+                    }
+                    "QStartNoAckMode" => "OK".to_string(),
+                    "qAttached" => "1".to_string(),
+                    "qC" => "QC1".to_string(),
+                    "qfThreadInfo" => "m1".to_string(),
+                    "qsThreadInfo" => "l".to_string(),
+                    _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                        let range = packet["qXfer:features:read:target.xml:".len()..]
+                            .split_once(',')
+                            .and_then(|(offset, len)| {
+                                Some((usize::from_str_radix(offset, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+                            });
+                        match range {
+                            Some((offset, len)) => read_chunk(&target_xml(), offset, len),
+                            None => error,
+                        }
+                    }
+                    // not supported
+                    _ => String::new(),
+                }
+            }
+        };
+        Some(reply)
+    }
+
+    /// Sends a packet to the client, dropping the connection if that fails
+    fn send(&mut self, data: &str) {
+        let Some(client) = &mut self.client else {
+            return;
+        };
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        if let Err(why) = write!(client, "${data}#{checksum:02x}") {
+            println!("Failed to send to GDB: {why}");
+            self.client = None;
+        }
+    }
+}
+
+/// Reads packets from a client until it disconnects, acknowledging them as
+/// they come in
+///
+/// Fails only when the emulator thread has gone away.
+fn read_packets(stream: TcpStream, events: &Sender<Event>) -> Result<(), ()> {
+    let mut ack_stream = stream.try_clone().map_err(|_| ())?;
+    events.send(Event::Connected(stream.try_clone().map_err(|_| ())?)).map_err(|_| ())?;
+    let mut bytes = BufReader::new(stream).bytes().map_while(Result::ok);
+    let mut acking = true;
+    while let Some(byte) = bytes.next() {
+        match byte {
+            0x03 => events.send(Event::Interrupt).map_err(|_| ())?,
+            b'$' => {
+                let data: Vec<u8> = bytes.by_ref().take_while(|&byte| byte != b'#').collect();
+                let checksum: Vec<u8> = bytes.by_ref().take(2).collect();
+                let valid = std::str::from_utf8(&checksum)
+                    .ok()
+                    .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                    .is_some_and(|checksum| checksum == data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)));
+                if acking {
+                    let _ = ack_stream.write_all(if valid { b"+" } else { b"-" });
+                }
+                if !valid {
+                    continue;
+                }
+                let packet = String::from_utf8_lossy(&data).into_owned();
+                if packet == "QStartNoAckMode" {
+                    acking = false;
+                }
+                events.send(Event::Packet(packet)).map_err(|_| ())?;
+            }
+            // acknowledgements, nothing is ever resent
+            _ => {}
+        }
+    }
+    events.send(Event::Disconnected).map_err(|_| ())
+}
+
+fn read_register(emulator: &Chip8, n: usize) -> Vec<u8> {
+    match n {
+        REG_I => emulator.index().to_le_bytes().to_vec(),
+        REG_PC => emulator.pc().to_le_bytes().to_vec(),
+        REG_SP => vec![emulator.stack().len() as u8],
+        _ => vec![emulator.registers()[n]],
+    }
+}
+
+fn write_register(emulator: &mut Chip8, n: usize, value: &[u8]) {
+    match n {
+        REG_I => emulator.set_index(u16::from_le_bytes([value[0], value[1]])),
+        REG_PC => emulator.set_pc(u16::from_le_bytes([value[0], value[1]])),
+        REG_SP => emulator.set_stack_depth(value[0] as usize),
+        _ => emulator.set_register(n, value[0]),
+    }
+}
+
+/// Describes the registers to the client
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\
+        <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+        <target version=\"1.0\">\
+        <architecture>chip8</architecture>\
+        <feature name=\"org.chip8.core\">"
+    );
+    for x in 0..N_REGISTERS {
+        xml += &format!("<reg name=\"v{x:x}\" bitsize=\"8\" type=\"uint8\"/>");
+    }
+    xml += "<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>";
+    xml += "<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>";
+    xml += "<reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>";
+    xml += "</feature></target>";
+    xml
+}
+
+/// Reply to a `qXfer` read, `m` if there's more to come and `l` for the last
+/// chunk
+fn read_chunk(data: &str, offset: usize, len: usize) -> String {
+    let start = offset.min(data.len());
+    let end = start.saturating_add(len).min(data.len());
+    let marker = if end < data.len() { 'm' } else { 'l' };
+    format!("{marker}{}", &data[start..end])
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{ Duration, Instant };
+
+    use super::*;
+
+    /// A client connected to a stub, with the emulator it drives
+    struct Client {
+        stream: TcpStream,
+        stub: GdbStub,
+        emulator: Chip8,
+        /// Bytes received but not consumed yet
+        received: Vec<u8>,
+    }
+
+    impl Client {
+        fn connect(rom: &[u8]) -> Self {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let (event_tx, events) = mpsc::channel();
+            thread::spawn(move || {
+                let (stream, _) = listener.accept().unwrap();
+                let _ = read_packets(stream, &event_tx);
+            });
+            let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+
+            let mut emulator = Chip8::new(700);
+            emulator.load_rom_bytes(rom).unwrap();
+            let stub = GdbStub {
+                events,
+                client: None,
+                paused: true,
+                breakpoints: BTreeSet::new(),
+                watchpoints: Vec::new(),
+            };
+            let mut client = Self { stream, stub, emulator, received: Vec::new() };
+            client.wait_until(|client| client.stub.client.is_some());
+            client
+        }
+
+        /// Polls the stub and reads its replies until `done`
+        fn wait_until(&mut self, done: impl Fn(&Self) -> bool) {
+            let start = Instant::now();
+            while !done(self) {
+                assert!(start.elapsed() < Duration::from_secs(5), "timed out, got {:?}", self.text());
+                self.stub.poll(&mut self.emulator, &mut ());
+                let mut buf = [0; 4096];
+                if let Ok(n) = self.stream.read(&mut buf) {
+                    self.received.extend_from_slice(&buf[..n]);
+                }
+            }
+        }
+
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.received).into_owned()
+        }
+
+        fn send_raw(&mut self, bytes: &[u8]) {
+            self.stream.write_all(bytes).unwrap();
+        }
+
+        /// Takes the next `n` bytes received
+        fn receive(&mut self, n: usize) -> String {
+            self.wait_until(|client| client.received.len() >= n);
+            let bytes: Vec<u8> = self.received.drain(..n).collect();
+            String::from_utf8(bytes).unwrap()
+        }
+
+        /// Takes the next packet received, checking its framing
+        fn receive_packet(&mut self) -> String {
+            self.wait_until(|client| {
+                let end = client.received.iter().position(|&byte| byte == b'#');
+                end.is_some_and(|end| client.received.len() >= end + 3)
+            });
+            let end = self.received.iter().position(|&byte| byte == b'#').unwrap();
+            let packet = self.receive(end + 3);
+            let data = &packet[1..end];
+            assert_eq!(packet, frame(data));
+            data.to_string()
+        }
+
+        /// Sends a packet, checking for its acknowledgement, and returns the reply
+        fn packet(&mut self, data: &str) -> String {
+            self.send_raw(frame(data).as_bytes());
+            assert_eq!(self.receive(1), "+");
+            self.receive_packet()
+        }
+    }
+
+    fn frame(data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        format!("${data}#{checksum:02x}")
+    }
+
+    /// LD V0, 0x12, LD I, 0x345, JP 0x204
+    const ROM: [u8; 6] = [0x60, 0x12, 0xa3, 0x45, 0x12, 0x04];
+
+    #[test]
+    fn framing_and_acks() {
+        let mut client = Client::connect(&ROM);
+        client.send_raw(b"$?#3f");
+        assert_eq!(client.receive(8), "+$S05#b8");
+
+        // acks from the client and noise between packets are ignored, and
+        // packets can arrive together
+        client.send_raw(b"+junk$qC#b4+$qAttached#8f");
+        let replies = format!("{}{}", frame("QC1"), frame("1"));
+        // (the second ack can come before or after the first reply)
+        let received = client.receive(2 + replies.len());
+        assert_eq!(received.replace('+', ""), replies);
+        assert_eq!(received.matches('+').count(), 2);
+
+        // unknown packets get an empty reply
+        assert_eq!(client.packet("vMustReplyEmpty"), "");
+    }
+
+    #[test]
+    fn bad_checksums_are_nacked() {
+        let mut client = Client::connect(&ROM);
+        client.send_raw(b"$?#00");
+        assert_eq!(client.receive(1), "-");
+        client.send_raw(b"$?#zz");
+        assert_eq!(client.receive(1), "-");
+        // and never handled
+        assert_eq!(client.packet("?"), "S05");
+        assert!(client.received.is_empty());
+    }
+
+    #[test]
+    fn no_ack_mode() {
+        let mut client = Client::connect(&ROM);
+        let supported = client.packet("qSupported:multiprocess+;swbreak+");
+        assert_eq!(supported, "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+");
+        assert_eq!(client.packet("QStartNoAckMode"), "OK");
+
+        client.send_raw(frame("?").as_bytes());
+        assert_eq!(client.receive_packet(), "S05");
+        // not even for bad packets
+        client.send_raw(b"$?#00");
+        client.send_raw(frame("qC").as_bytes());
+        assert_eq!(client.receive_packet(), "QC1");
+    }
+
+    #[test]
+    fn registers() {
+        let mut client = Client::connect(&ROM);
+        let zeros = "00".repeat(15);
+        assert_eq!(client.packet("g"), format!("00{zeros}0000000200"));
+        assert_eq!(client.packet("s"), "S05");
+        assert_eq!(client.packet("s"), "S05");
+        // V0-VF, then I and PC little endian, then SP
+        assert_eq!(client.packet("g"), format!("12{zeros}4503040200"));
+
+        let values: String = (1..=16).map(|value| format!("{value:02x}")).collect();
+        assert_eq!(client.packet(&format!("G{values}2301000300")), "OK");
+        assert_eq!(client.emulator.registers()[..], (1..=16).collect::<Vec<u8>>());
+        assert_eq!((client.emulator.index(), client.emulator.pc()), (0x123, 0x300));
+        for (n, value) in [("0", "01"), ("f", "10"), ("10", "2301"), ("11", "0003"), ("12", "00")] {
+            assert_eq!(client.packet(&format!("p{n}")), value);
+        }
+
+        assert_eq!(client.packet("P0=aa"), "OK");
+        assert_eq!(client.packet("P10=3412"), "OK");
+        assert_eq!((client.emulator.registers()[0], client.emulator.index()), (0xaa, 0x1234));
+        for packet in ["p13", "px", "P0=aabb", "P10=34", "P13=00", "P0", "Gzz"] {
+            assert_eq!(client.packet(packet), "E01", "{packet}");
+        }
+    }
+
+    #[test]
+    fn memory() {
+        let mut client = Client::connect(&ROM);
+        assert_eq!(client.packet("m200,6"), "6012a3451204");
+        assert_eq!(client.packet("M300,2:abcd"), "OK");
+        assert_eq!(client.emulator.memory()[0x300..0x302], [0xab, 0xcd]);
+        assert_eq!(client.packet("mfffe,2"), "0000");
+        // lengths running past the end are cut short rather than overflowing
+        assert_eq!(client.packet("mffff,ffffffffffffffff"), "00");
+
+        // out of range, or not matching the data
+        for packet in [
+            "m10000,1", "mzz,1", "m200", "M10000,1:00", "Mffff,2:0000", "M300,3:abcd", "M300,1:a",
+            "Mffff,ffffffffffffffff:00",
+        ] {
+            assert_eq!(client.packet(packet), "E01", "{packet}");
+        }
+        assert_eq!(client.emulator.memory()[0xffff], 0);
+    }
+
+    #[test]
+    fn breakpoints_and_continue() {
+        let mut client = Client::connect(&ROM);
+        assert_eq!(client.packet("Z0,204,2"), "OK");
+        assert_eq!(client.packet("Z1,202,2"), "OK");
+        assert_eq!(client.emulator.breakpoints().collect::<Vec<_>>(), [0x202, 0x204]);
+        assert_eq!(client.packet("z1,202,2"), "OK");
+        assert_eq!(client.packet("Z5,300,1"), "");
+        assert_eq!(client.packet("Z0,zz,2"), "E01");
+
+        // continuing has no reply until execution stops
+        client.send_raw(frame("c").as_bytes());
+        assert_eq!(client.receive(1), "+");
+        client.wait_until(|client| !client.stub.paused());
+        let result = client.emulator.run_cycles(10);
+        client.stub.frame_done(&mut client.emulator, &result);
+        assert_eq!(client.receive_packet(), "S05");
+        assert!(client.stub.paused());
+        assert_eq!(client.emulator.pc(), 0x204);
+
+        // the client's breakpoints go away with it
+        client.emulator.add_breakpoint(0x300);
+        assert_eq!(client.packet("D"), "OK");
+        assert!(!client.stub.paused());
+        assert_eq!(client.emulator.breakpoints().collect::<Vec<_>>(), [0x300]);
+    }
+
+    #[test]
+    fn target_description() {
+        let mut client = Client::connect(&ROM);
+        let xml = target_xml();
+        let first = client.packet("qXfer:features:read:target.xml:0,10");
+        assert_eq!(first, format!("m{}", &xml[..0x10]));
+        let rest = client.packet(&format!("qXfer:features:read:target.xml:10,{:x}", xml.len()));
+        assert_eq!(rest, format!("l{}", &xml[0x10..]));
+        assert!(xml.contains("<architecture>chip8</architecture>"));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+    }
+
+    #[test]
+    fn kill_quits() {
+        let mut client = Client::connect(&ROM);
+        client.send_raw(frame("k").as_bytes());
+        let start = Instant::now();
+        while !client.stub.poll(&mut client.emulator, &mut ()).quit {
+            assert!(start.elapsed() < Duration::from_secs(5), "never quit");
+        }
+        // acknowledged, but with no reply before the next packet's
+        assert_eq!(client.receive(1), "+");
+        assert_eq!(client.packet("?"), "S05");
+    }
+
+    #[test]
+    fn watchpoints() {
+        // LD I, 0x300, LD [I], V1, LD V2, [I], JP 0x206
+        let rom = [0xa3, 0x00, 0xf1, 0x55, 0xf2, 0x65, 0x12, 0x06];
+        let mut client = Client::connect(&rom);
+        assert_eq!(client.packet("Z2,301,1"), "OK");
+        assert_eq!(client.packet("Z3,302,2"), "OK");
+        assert_eq!(client.packet("Z4,300,1"), "OK");
+        assert_eq!(client.packet("Z2,300,0"), "E01");
+        assert_eq!(client.emulator.watchpoints().len(), 3);
+
+        // the first watchpoint hit is reported, at the first address it watches
+        assert_eq!(client.packet("s"), "S05");
+        assert_eq!(client.packet("s"), "T05watch:301;");
+        assert_eq!(client.packet("z2,301,1"), "OK");
+        assert_eq!(client.packet("s"), "T05rwatch:302;");
+        assert_eq!(client.packet("z3,302,2"), "OK");
+        assert_eq!(client.packet("s204"), "T05awatch:300;");
+        assert_eq!(client.emulator.pc(), 0x206);
+        assert!(client.emulator.take_watch_hits().is_empty());
+
+        // the client's watchpoints go away with it
+        assert_eq!(client.packet("D"), "OK");
+        assert!(client.emulator.watchpoints().is_empty());
+    }
+}
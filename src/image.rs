@@ -0,0 +1,257 @@
+use crate::*;
+use crate::state::crc32;
+
+/// Size of the visible part of the framebuffer
+fn visible_size(hires: bool) -> (usize, usize) {
+    if hires { (HIRES_SCREEN_W, HIRES_SCREEN_H) } else { (SCREEN_W, SCREEN_H) }
+}
+
+/// Draws the visible part of the framebuffer as text, one line per row
+///
+/// Unlit pixels are `.` and lit ones `#`, with XO-CHIP's second plane as `+`
+/// and both planes as `%`.
+pub fn framebuffer_to_text(pixel_buf: &PixelBuf, hires: bool) -> String {
+    let (w, h) = visible_size(hires);
+    let mut text = String::with_capacity((w + 1) * h);
+    for row in pixel_buf[..h].iter() {
+        text.extend(row[..w].iter().map(|pixel| ['.', '#', '+', '%'][(pixel & 0x3) as usize]));
+        text.push('\n');
+    }
+    text
+}
+
+/// Encodes the visible part of the framebuffer as a binary PBM (`P4`) image,
+/// any lit plane counting as black
+pub fn framebuffer_to_pbm(pixel_buf: &PixelBuf, hires: bool) -> Vec<u8> {
+    let (w, h) = visible_size(hires);
+    let mut pbm = format!("P4\n{w} {h}\n").into_bytes();
+    for row in pixel_buf[..h].iter() {
+        for pixels in row[..w].chunks(8) {
+            let byte = pixels
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &pixel)| if pixel != 0 { byte | (0x80 >> i) } else { byte });
+            pbm.push(byte);
+        }
+    }
+    pbm
+}
+
+/// Hashes the resolution and visible part of the framebuffer, for comparing
+/// against known good output
+pub fn framebuffer_hash(pixel_buf: &PixelBuf, hires: bool) -> u64 {
+    let (w, h) = visible_size(hires);
+    let mut bytes = vec![hires as u8];
+    for row in pixel_buf[..h].iter() {
+        bytes.extend_from_slice(&row[..w]);
+    }
+    rom_hash(&bytes)
+}
+
+/// Encodes the visible part of the framebuffer as a PNG image
+///
+/// Every pixel becomes a `scale` x `scale` square, coloured by `palette`
+/// indexed by the pixel value (unlit, first plane, second plane, both), as
+/// RGBA with the alpha ignored.
+pub fn framebuffer_to_png(pixel_buf: &PixelBuf, hires: bool, scale: usize, palette: &[[u8; 4]; 4]) -> Vec<u8> {
+    let (w, h) = visible_size(hires);
+    let scale = scale.max(1);
+    let mut rgb = Vec::with_capacity(w * h * scale * scale * 3);
+    for row in pixel_buf[..h].iter() {
+        for _ in 0..scale {
+            for pixel in row[..w].iter() {
+                let color = &palette[(pixel & 0x3) as usize][..3];
+                for _ in 0..scale {
+                    rgb.extend_from_slice(color);
+                }
+            }
+        }
+    }
+    encode_png(w * scale, h * scale, &rgb)
+}
+
+/// Encodes 8 bit RGB pixels as a PNG image
+///
+/// The image data is stored uncompressed, which is plenty for screens this
+/// small and saves pulling in a deflate implementation.
+fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    // each row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // zlib stream of stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    while let Some(block) = blocks.next() {
+        zlib.push(if blocks.peek().is_none() { 1 } else { 0 });
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bit depth, RGB, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"IDAT", &zlib);
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALETTE: [[u8; 4]; 4] = [[0, 0, 0, 255], [255, 255, 255, 255], [255, 0, 0, 255], [0, 0, 255, 255]];
+
+    /// A pixel of each plane combination in the low resolution corners, and
+    /// one only visible in high resolution
+    fn screen() -> PixelBuf {
+        let mut pixel_buf = [[0; HIRES_SCREEN_W]; HIRES_SCREEN_H];
+        pixel_buf[0][0] = 1;
+        pixel_buf[0][1] = 2;
+        pixel_buf[31][63] = 3;
+        pixel_buf[40][100] = 1;
+        pixel_buf
+    }
+
+    /// Splits a PNG into its chunks, checking their CRCs
+    fn chunks(png: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut chunks = vec![];
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&rest[4..8 + len]));
+            chunks.push((kind, data));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn text_dump() {
+        let text = framebuffer_to_text(&screen(), false);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), SCREEN_H);
+        assert_eq!(lines[0], format!("#+{}", ".".repeat(62)));
+        assert_eq!(lines[1], ".".repeat(64));
+        assert_eq!(lines[31], format!("{}%", ".".repeat(63)));
+
+        let text = framebuffer_to_text(&screen(), true);
+        assert_eq!(text.lines().count(), HIRES_SCREEN_H);
+        assert_eq!(text.lines().nth(40).unwrap().find('#'), Some(100));
+    }
+
+    #[test]
+    fn pbm_dump() {
+        let pbm = framebuffer_to_pbm(&screen(), false);
+        let (header, bits) = pbm.split_at(9);
+        assert_eq!(header, b"P4\n64 32\n");
+        assert_eq!(bits.len(), 8 * 32);
+        assert_eq!(bits[..8], [0xc0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bits[bits.len() - 1], 0x01);
+        assert_eq!(bits.iter().filter(|&&byte| byte != 0).count(), 2);
+
+        let pbm = framebuffer_to_pbm(&screen(), true);
+        assert_eq!(pbm.len(), 10 + 16 * 64);
+        assert_eq!(pbm[10 + 40 * 16 + 12], 0x08);
+    }
+
+    #[test]
+    fn hash_covers_the_visible_screen_only() {
+        let blank = [[0; HIRES_SCREEN_W]; HIRES_SCREEN_H];
+        assert_eq!(framebuffer_hash(&blank, false), rom_hash(&[0; 1 + SCREEN_W * SCREEN_H]));
+        assert_ne!(framebuffer_hash(&blank, false), framebuffer_hash(&blank, true));
+
+        let mut hidden = blank;
+        hidden[40][100] = 1;
+        assert_eq!(framebuffer_hash(&hidden, false), framebuffer_hash(&blank, false));
+        assert_ne!(framebuffer_hash(&hidden, true), framebuffer_hash(&blank, true));
+        assert_ne!(framebuffer_hash(&screen(), false), framebuffer_hash(&blank, false));
+    }
+
+    #[test]
+    fn png_structure() {
+        // 12x scaled, so the image data needs several stored blocks
+        let scale = 12;
+        let png = framebuffer_to_png(&screen(), false, scale, &PALETTE);
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']);
+
+        let chunks = chunks(&png);
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        let (w, h) = (SCREEN_W * scale, SCREEN_H * scale);
+        let mut header = (w as u32).to_be_bytes().to_vec();
+        header.extend_from_slice(&(h as u32).to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        assert_eq!(chunks[0].1, header);
+        assert!(chunks[2].1.is_empty());
+
+        // zlib header, stored blocks of at most 65535 bytes, then the adler32
+        let zlib = chunks[1].1;
+        assert_eq!(zlib[..2], [0x78, 0x01]);
+        let mut raw = vec![];
+        let mut lens = vec![];
+        let mut pos = 2;
+        loop {
+            let last = zlib[pos];
+            let len = u16::from_le_bytes([zlib[pos + 1], zlib[pos + 2]]);
+            assert_eq!(u16::from_le_bytes([zlib[pos + 3], zlib[pos + 4]]), !len);
+            raw.extend_from_slice(&zlib[pos + 5..pos + 5 + len as usize]);
+            lens.push(len);
+            pos += 5 + len as usize;
+            if last == 1 {
+                break;
+            }
+            assert_eq!(last, 0);
+        }
+        let total = (w * 3 + 1) * h;
+        assert_eq!(raw.len(), total);
+        assert_eq!(lens.len(), total.div_ceil(0xffff));
+        assert!(lens[..lens.len() - 1].iter().all(|&len| len == 0xffff));
+        assert_eq!(zlib[pos..], adler32(&raw).to_be_bytes());
+
+        // every row is unfiltered, the first one starting with a white then a
+        // red square
+        let row = w * 3 + 1;
+        assert!(raw.chunks(row).all(|row| row[0] == 0));
+        assert_eq!(raw[1..4], [255, 255, 255]);
+        assert_eq!(raw[1 + scale * 3..4 + scale * 3], [255, 0, 0]);
+        assert_eq!(raw[1 + scale * 6..4 + scale * 6], [0, 0, 0]);
+        assert_eq!(raw[total - 3..], [0, 0, 255]);
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(b""), 1);
+    }
+}
@@ -0,0 +1,441 @@
+use std::fmt;
+
+/// A decoded CHIP-8 instruction
+///
+/// Register operands are indices (`0x0..=0xF`) into V0-VF. The comment on each
+/// variant is the raw opcode pattern it decodes from.
+///
+/// Every instruction is one word long, except for the XO-CHIP `F000 NNNN`
+/// which takes its address from the following word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN - Call machine code routine
+    Sys(u16),
+    /// 00E0 - Clear screen
+    Cls,
+    /// 00EE - Return from subroutine
+    Ret,
+    /// 00CN - Scroll down N pixels (SUPER-CHIP)
+    Scd(u8),
+    /// 00DN - Scroll up N pixels (XO-CHIP)
+    Scu(u8),
+    /// 00FB - Scroll right 4 pixels (SUPER-CHIP)
+    Scr,
+    /// 00FC - Scroll left 4 pixels (SUPER-CHIP)
+    Scl,
+    /// 00FD - Exit the interpreter (SUPER-CHIP)
+    Exit,
+    /// 00FE - Switch to 64x32 low resolution (SUPER-CHIP)
+    Low,
+    /// 00FF - Switch to 128x64 high resolution (SUPER-CHIP)
+    High,
+    /// 1NNN - Jump
+    Jp(u16),
+    /// 2NNN - Call subroutine
+    Call(u16),
+    /// 3XNN - Skip if VX == NN
+    SeImm(u8, u8),
+    /// 4XNN - Skip if VX != NN
+    SneImm(u8, u8),
+    /// 5XY0 - Skip if VX == VY
+    SeReg(u8, u8),
+    /// 5XY2 - Store VX-VY at I (XO-CHIP)
+    SaveRange(u8, u8),
+    /// 5XY3 - Load VX-VY from I (XO-CHIP)
+    LoadRange(u8, u8),
+    /// 6XNN - VX = NN
+    LdImm(u8, u8),
+    /// 7XNN - VX += NN
+    AddImm(u8, u8),
+    /// 8XY0 - VX = VY
+    LdReg(u8, u8),
+    /// 8XY1 - VX |= VY
+    Or(u8, u8),
+    /// 8XY2 - VX &= VY
+    And(u8, u8),
+    /// 8XY3 - VX ^= VY
+    Xor(u8, u8),
+    /// 8XY4 - VX += VY
+    AddReg(u8, u8),
+    /// 8XY5 - VX -= VY
+    Sub(u8, u8),
+    /// 8XY6 - VX >>= 1
+    Shr(u8, u8),
+    /// 8XY7 - VX = VY - VX
+    Subn(u8, u8),
+    /// 8XYE - VX <<= 1
+    Shl(u8, u8),
+    /// 9XY0 - Skip if VX != VY
+    SneReg(u8, u8),
+    /// ANNN - I = NNN
+    LdI(u16),
+    /// BNNN - Jump to NNN + V0
+    JpV0(u16),
+    /// CXNN - VX = random & NN
+    Rnd(u8, u8),
+    /// DXYN - Draw N tall sprite at VX, VY (DXY0 draws a 16x16 sprite)
+    Drw(u8, u8, u8),
+    /// EX9E - Skip if key VX is pressed
+    Skp(u8),
+    /// EXA1 - Skip if key VX isn't pressed
+    Sknp(u8),
+    /// F000 NNNN - I = NNNN (XO-CHIP)
+    LdILong(u16),
+    /// FN01 - Select drawing planes N (XO-CHIP)
+    Plane(u8),
+    /// F002 - Load 16 byte audio pattern from I (XO-CHIP)
+    Audio,
+    /// FX07 - VX = delay timer
+    LdVxDt(u8),
+    /// FX0A - Block for keypress, store in VX
+    LdVxK(u8),
+    /// FX15 - Delay timer = VX
+    LdDtVx(u8),
+    /// FX18 - Sound timer = VX
+    LdStVx(u8),
+    /// FX1E - I += VX
+    AddIVx(u8),
+    /// FX29 - I = font sprite for VX
+    LdFVx(u8),
+    /// FX30 - I = large font sprite for VX (SUPER-CHIP)
+    LdHfVx(u8),
+    /// FX33 - Binary coded decimal of VX at I
+    LdBVx(u8),
+    /// FX3A - Audio pattern pitch = VX (XO-CHIP)
+    Pitch(u8),
+    /// FX55 - Store V0-VX at I
+    LdIVx(u8),
+    /// FX65 - Load V0-VX from I
+    LdVxI(u8),
+    /// FX75 - Store V0-VX in the RPL user flags (SUPER-CHIP)
+    LdRVx(u8),
+    /// FX85 - Load V0-VX from the RPL user flags (SUPER-CHIP)
+    LdVxR(u8),
+}
+
+impl Instruction {
+    /// Decodes a raw instruction word, returns `None` if it isn't a valid instruction
+    ///
+    /// `F000 NNNN` decodes with an address of 0, since that lives in the next
+    /// word, see [`Instruction::decode_at`].
+    pub fn decode(opcode: u16) -> Option<Self> {
+        // deconstructing instruction
+        let nibbles: [u8; 4] = [
+            (opcode >> 12) as u8,
+            ((opcode >> 8) & 0xf) as u8,
+            ((opcode >> 4) & 0xf) as u8,
+            (opcode & 0xf) as u8,
+        ];
+
+        // Register identifiers
+        let X = nibbles[1];
+        let Y = nibbles[2];
+
+        // Values/constants
+        let N = nibbles[3];
+        let NN = (opcode & 0x00ff) as u8;
+        let NNN = opcode & 0x0fff;
+
+        use Instruction::*;
+        let instruction = match nibbles[0] {
+            0x0 => {
+                match NNN {
+                    0x0e0 => Cls,
+                    0x0ee => Ret,
+                    0x0c0..=0x0cf => Scd(N),
+                    0x0d0..=0x0df => Scu(N),
+                    0x0fb => Scr,
+                    0x0fc => Scl,
+                    0x0fd => Exit,
+                    0x0fe => Low,
+                    0x0ff => High,
+                    _ => Sys(NNN),
+                }
+            }
+            0x1 => Jp(NNN),
+            0x2 => Call(NNN),
+            0x3 => SeImm(X, NN),
+            0x4 => SneImm(X, NN),
+            0x5 => {
+                match N {
+                    0x0 => SeReg(X, Y),
+                    0x2 => SaveRange(X, Y),
+                    0x3 => LoadRange(X, Y),
+                    _ => {
+                        return None;
+                    }
+                }
+            }
+            0x6 => LdImm(X, NN),
+            0x7 => AddImm(X, NN),
+            0x8 => {
+                match N {
+                    0x0 => LdReg(X, Y),
+                    0x1 => Or(X, Y),
+                    0x2 => And(X, Y),
+                    0x3 => Xor(X, Y),
+                    0x4 => AddReg(X, Y),
+                    0x5 => Sub(X, Y),
+                    0x6 => Shr(X, Y),
+                    0x7 => Subn(X, Y),
+                    0xe => Shl(X, Y),
+                    _ => {
+                        return None;
+                    }
+                }
+            }
+            0x9 if N == 0x0 => SneReg(X, Y),
+            0xa => LdI(NNN),
+            0xb => JpV0(NNN),
+            0xc => Rnd(X, NN),
+            0xd => Drw(X, Y, N),
+            0xe => {
+                match NN {
+                    0x9e => Skp(X),
+                    0xa1 => Sknp(X),
+                    _ => {
+                        return None;
+                    }
+                }
+            }
+            0xf => {
+                match NN {
+                    0x00 if X == 0x0 => LdILong(0),
+                    0x01 => Plane(X),
+                    0x02 if X == 0x0 => Audio,
+                    0x07 => LdVxDt(X),
+                    0x0a => LdVxK(X),
+                    0x15 => LdDtVx(X),
+                    0x18 => LdStVx(X),
+                    0x1e => AddIVx(X),
+                    0x29 => LdFVx(X),
+                    0x30 => LdHfVx(X),
+                    0x33 => LdBVx(X),
+                    0x3a => Pitch(X),
+                    0x55 => LdIVx(X),
+                    0x65 => LdVxI(X),
+                    0x75 => LdRVx(X),
+                    0x85 => LdVxR(X),
+                    _ => {
+                        return None;
+                    }
+                }
+            }
+            _ => {
+                return None;
+            }
+        };
+        Some(instruction)
+    }
+
+    /// Decodes the instruction at the start of `bytes`, including the second
+    /// word of `F000 NNNN`
+    pub fn decode_at(bytes: &[u8]) -> Option<Self> {
+        let opcode = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]);
+        match Instruction::decode(opcode)? {
+            Instruction::LdILong(_) => {
+                let addr = u16::from_be_bytes([*bytes.get(2)?, *bytes.get(3)?]);
+                Some(Instruction::LdILong(addr))
+            }
+            instruction => Some(instruction),
+        }
+    }
+
+    /// Length in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdILong(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// Encodes into the bytes stored in memory (2, or 4 for `F000 NNNN`)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.encode().to_be_bytes().to_vec();
+        if let Instruction::LdILong(addr) = self {
+            bytes.extend_from_slice(&addr.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Encodes back into a raw instruction word (just the first word of `F000 NNNN`)
+    pub fn encode(&self) -> u16 {
+        // helpers for packing operands into the usual opcode layouts
+        let xnn = |op: u16, x: u8, nn: u8| (op << 12) | ((x as u16 & 0xf) << 8) | (nn as u16);
+        let xyn = |op: u16, x: u8, y: u8, n: u8| {
+            (op << 12) | ((x as u16 & 0xf) << 8) | ((y as u16 & 0xf) << 4) | (n as u16 & 0xf)
+        };
+        let nnn = |op: u16, nnn: u16| (op << 12) | (nnn & 0x0fff);
+
+        use Instruction::*;
+        match *self {
+            Sys(addr) => nnn(0x0, addr),
+            Cls => 0x00e0,
+            Ret => 0x00ee,
+            Scd(n) => 0x00c0 | (n as u16 & 0xf),
+            Scu(n) => 0x00d0 | (n as u16 & 0xf),
+            Scr => 0x00fb,
+            Scl => 0x00fc,
+            Exit => 0x00fd,
+            Low => 0x00fe,
+            High => 0x00ff,
+            Jp(addr) => nnn(0x1, addr),
+            Call(addr) => nnn(0x2, addr),
+            SeImm(x, nn) => xnn(0x3, x, nn),
+            SneImm(x, nn) => xnn(0x4, x, nn),
+            SeReg(x, y) => xyn(0x5, x, y, 0x0),
+            SaveRange(x, y) => xyn(0x5, x, y, 0x2),
+            LoadRange(x, y) => xyn(0x5, x, y, 0x3),
+            LdImm(x, nn) => xnn(0x6, x, nn),
+            AddImm(x, nn) => xnn(0x7, x, nn),
+            LdReg(x, y) => xyn(0x8, x, y, 0x0),
+            Or(x, y) => xyn(0x8, x, y, 0x1),
+            And(x, y) => xyn(0x8, x, y, 0x2),
+            Xor(x, y) => xyn(0x8, x, y, 0x3),
+            AddReg(x, y) => xyn(0x8, x, y, 0x4),
+            Sub(x, y) => xyn(0x8, x, y, 0x5),
+            Shr(x, y) => xyn(0x8, x, y, 0x6),
+            Subn(x, y) => xyn(0x8, x, y, 0x7),
+            Shl(x, y) => xyn(0x8, x, y, 0xe),
+            SneReg(x, y) => xyn(0x9, x, y, 0x0),
+            LdI(addr) => nnn(0xa, addr),
+            JpV0(addr) => nnn(0xb, addr),
+            Rnd(x, nn) => xnn(0xc, x, nn),
+            Drw(x, y, n) => xyn(0xd, x, y, n),
+            Skp(x) => xnn(0xe, x, 0x9e),
+            Sknp(x) => xnn(0xe, x, 0xa1),
+            LdILong(_) => 0xf000,
+            Plane(n) => xnn(0xf, n, 0x01),
+            Audio => 0xf002,
+            LdVxDt(x) => xnn(0xf, x, 0x07),
+            LdVxK(x) => xnn(0xf, x, 0x0a),
+            LdDtVx(x) => xnn(0xf, x, 0x15),
+            LdStVx(x) => xnn(0xf, x, 0x18),
+            AddIVx(x) => xnn(0xf, x, 0x1e),
+            LdFVx(x) => xnn(0xf, x, 0x29),
+            LdHfVx(x) => xnn(0xf, x, 0x30),
+            LdBVx(x) => xnn(0xf, x, 0x33),
+            Pitch(x) => xnn(0xf, x, 0x3a),
+            LdIVx(x) => xnn(0xf, x, 0x55),
+            LdVxI(x) => xnn(0xf, x, 0x65),
+            LdRVx(x) => xnn(0xf, x, 0x75),
+            LdVxR(x) => xnn(0xf, x, 0x85),
+        }
+    }
+}
+
+/// Disassembles into the common (Cowgod style) mnemonics, e.g. `LD V1, 0x0A`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            Sys(addr) => write!(f, "SYS 0x{addr:03X}"),
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Scd(n) => write!(f, "SCD {n}"),
+            Scu(n) => write!(f, "SCU {n}"),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            Jp(addr) => write!(f, "JP 0x{addr:03X}"),
+            Call(addr) => write!(f, "CALL 0x{addr:03X}"),
+            SeImm(x, nn) => write!(f, "SE V{x:X}, 0x{nn:02X}"),
+            SneImm(x, nn) => write!(f, "SNE V{x:X}, 0x{nn:02X}"),
+            SeReg(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            SaveRange(x, y) => write!(f, "SAVE V{x:X}, V{y:X}"),
+            LoadRange(x, y) => write!(f, "LOAD V{x:X}, V{y:X}"),
+            LdImm(x, nn) => write!(f, "LD V{x:X}, 0x{nn:02X}"),
+            AddImm(x, nn) => write!(f, "ADD V{x:X}, 0x{nn:02X}"),
+            LdReg(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            AddReg(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Sub(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Shr(x, y) => write!(f, "SHR V{x:X}, V{y:X}"),
+            Subn(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Shl(x, y) => write!(f, "SHL V{x:X}, V{y:X}"),
+            SneReg(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            LdI(addr) => write!(f, "LD I, 0x{addr:03X}"),
+            JpV0(addr) => write!(f, "JP V0, 0x{addr:03X}"),
+            Rnd(x, nn) => write!(f, "RND V{x:X}, 0x{nn:02X}"),
+            Drw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Skp(x) => write!(f, "SKP V{x:X}"),
+            Sknp(x) => write!(f, "SKNP V{x:X}"),
+            LdILong(addr) => write!(f, "LD I, LONG 0x{addr:04X}"),
+            Plane(n) => write!(f, "PLANE {n}"),
+            Audio => write!(f, "AUDIO"),
+            LdVxDt(x) => write!(f, "LD V{x:X}, DT"),
+            LdVxK(x) => write!(f, "LD V{x:X}, K"),
+            LdDtVx(x) => write!(f, "LD DT, V{x:X}"),
+            LdStVx(x) => write!(f, "LD ST, V{x:X}"),
+            AddIVx(x) => write!(f, "ADD I, V{x:X}"),
+            LdFVx(x) => write!(f, "LD F, V{x:X}"),
+            LdHfVx(x) => write!(f, "LD HF, V{x:X}"),
+            LdBVx(x) => write!(f, "LD B, V{x:X}"),
+            Pitch(x) => write!(f, "PITCH V{x:X}"),
+            LdIVx(x) => write!(f, "LD [I], V{x:X}"),
+            LdVxI(x) => write!(f, "LD V{x:X}, [I]"),
+            LdRVx(x) => write!(f, "LD R, V{x:X}"),
+            LdVxR(x) => write!(f, "LD V{x:X}, R"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode_round_trip() {
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{instruction}");
+            }
+        }
+    }
+
+    #[test]
+    fn decode_operands() {
+        assert_eq!(Instruction::decode(0x00e0), Some(Instruction::Cls));
+        assert_eq!(Instruction::decode(0x0123), Some(Instruction::Sys(0x123)));
+        assert_eq!(Instruction::decode(0x00c5), Some(Instruction::Scd(5)));
+        assert_eq!(Instruction::decode(0x1234), Some(Instruction::Jp(0x234)));
+        assert_eq!(Instruction::decode(0x6a05), Some(Instruction::LdImm(0xa, 0x05)));
+        assert_eq!(Instruction::decode(0x8ab4), Some(Instruction::AddReg(0xa, 0xb)));
+        assert_eq!(Instruction::decode(0xd12f), Some(Instruction::Drw(1, 2, 0xf)));
+        assert_eq!(Instruction::decode(0x5342), Some(Instruction::SaveRange(3, 4)));
+        assert_eq!(Instruction::decode(0xf233), Some(Instruction::LdBVx(2)));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_opcodes() {
+        for opcode in [0x5001, 0x8008, 0x9001, 0xe000, 0xf100, 0xf102, 0xffff] {
+            assert_eq!(Instruction::decode(opcode), None, "{opcode:#06X}");
+        }
+    }
+
+    #[test]
+    fn long_load_takes_two_words() {
+        let instruction = Instruction::decode_at(&[0xf0, 0x00, 0x12, 0x34]).unwrap();
+        assert_eq!(instruction, Instruction::LdILong(0x1234));
+        assert_eq!(instruction.size(), 4);
+        assert_eq!(instruction.to_bytes(), [0xf0, 0x00, 0x12, 0x34]);
+        assert_eq!(Instruction::decode_at(&[0xf0, 0x00, 0x12]), None);
+
+        assert_eq!(Instruction::decode_at(&[0x00, 0xe0, 0xf0]), Some(Instruction::Cls));
+        assert_eq!(Instruction::decode_at(&[0x00]), None);
+        assert_eq!(Instruction::Cls.to_bytes(), [0x00, 0xe0]);
+    }
+
+    #[test]
+    fn display_mnemonics() {
+        assert_eq!(Instruction::LdImm(0xa, 0x05).to_string(), "LD VA, 0x05");
+        assert_eq!(Instruction::JpV0(0x300).to_string(), "JP V0, 0x300");
+        assert_eq!(Instruction::Drw(1, 2, 0xf).to_string(), "DRW V1, V2, 15");
+        assert_eq!(Instruction::LdVxI(3).to_string(), "LD V3, [I]");
+        assert_eq!(Instruction::LdILong(0x1234).to_string(), "LD I, LONG 0x1234");
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use winit::{
+    event::KeyEvent,
+    keyboard::{ Key, KeyCode, PhysicalKey },
+};
+
+use crate::*;
+
+/// Built-in keymaps, usable by name with `--keymap` or `preset =` in a
+/// keymap file
+pub const PRESETS: [&str; 4] = ["physical", "azerty", "qwertz", "numpad"];
+
+/// Keys as laid out on the COSMAC VIP keypad, row by row
+const KEYPAD_LAYOUT: [usize; 16] = [0x1, 0x2, 0x3, 0xc, 0x4, 0x5, 0x6, 0xd, 0x7, 0x8, 0x9, 0xe, 0xa, 0x0, 0xb, 0xf];
+
+/// Keys that can be bound by name, the names being those of [`KeyCode`]
+const KEY_CODES: [KeyCode; 75] = [
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF,
+    KeyCode::KeyG, KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL,
+    KeyCode::KeyM, KeyCode::KeyN, KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR,
+    KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU, KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX,
+    KeyCode::KeyY, KeyCode::KeyZ,
+    KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+    KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+    KeyCode::NumpadAdd, KeyCode::NumpadSubtract, KeyCode::NumpadMultiply, KeyCode::NumpadDivide,
+    KeyCode::NumpadDecimal, KeyCode::NumpadEnter,
+    KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft, KeyCode::ArrowRight,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Tab, KeyCode::Escape,
+    KeyCode::ShiftLeft, KeyCode::ShiftRight, KeyCode::ControlLeft, KeyCode::ControlRight,
+    KeyCode::AltLeft, KeyCode::AltRight,
+    KeyCode::Comma, KeyCode::Period, KeyCode::Slash, KeyCode::Semicolon, KeyCode::Quote,
+    KeyCode::BracketLeft, KeyCode::BracketRight, KeyCode::Minus, KeyCode::Equal,
+];
+
+/// Which keyboard keys press which CHIP-8 keys
+///
+/// Keys are bound either by their position on the keyboard, named as in
+/// [`KeyCode`] (`KeyQ` is the key left of `W` on any layout), or by the
+/// character they type (`q`), so a layout's own labels can be used. Any
+/// number of keys can press the same CHIP-8 key.
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    physical: HashMap<KeyCode, usize>,
+    /// Characters, lowercase
+    logical: HashMap<String, usize>,
+}
+
+impl Keymap {
+    /// Built-in keymap by name, see [`PRESETS`]
+    pub fn preset(name: &str) -> Option<Self> {
+        let mut keymap = Keymap::default();
+        match name {
+            // the keypad's 4x4 grid on the left of the keyboard, wherever the
+            // letters are
+            "physical" => {
+                let grid = [
+                    KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+                    KeyCode::KeyQ, KeyCode::KeyW, KeyCode::KeyE, KeyCode::KeyR,
+                    KeyCode::KeyA, KeyCode::KeyS, KeyCode::KeyD, KeyCode::KeyF,
+                    KeyCode::KeyZ, KeyCode::KeyX, KeyCode::KeyC, KeyCode::KeyV,
+                ];
+                for (code, key) in grid.into_iter().zip(KEYPAD_LAYOUT) {
+                    keymap.physical.insert(code, key);
+                }
+            }
+            // the same grid by the letters printed on the keys, the digits
+            // need shift on AZERTY so they stay physical
+            "azerty" | "qwertz" => {
+                let letters = if name == "azerty" { "azerqsdfwxcv" } else { "qwerasdfyxcv" };
+                let digits = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4];
+                for (code, key) in digits.into_iter().zip(&KEYPAD_LAYOUT[..4]) {
+                    keymap.physical.insert(code, *key);
+                }
+                for (c, key) in letters.chars().zip(&KEYPAD_LAYOUT[4..]) {
+                    keymap.logical.insert(c.to_string(), *key);
+                }
+            }
+            // the numpad's rows are upside down compared to the keypad, so
+            // they're swapped round to keep 2/4/6/8 as up/left/right/down
+            "numpad" => {
+                let grid = [
+                    KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9, KeyCode::NumpadSubtract,
+                    KeyCode::Numpad4, KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::NumpadAdd,
+                    KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::NumpadEnter,
+                    KeyCode::NumpadDivide, KeyCode::Numpad0, KeyCode::NumpadMultiply, KeyCode::NumpadDecimal,
+                ];
+                for (code, key) in grid.into_iter().zip(KEYPAD_LAYOUT) {
+                    keymap.physical.insert(code, key);
+                }
+                let arrows = [(KeyCode::ArrowUp, 0x2), (KeyCode::ArrowLeft, 0x4), (KeyCode::ArrowRight, 0x6), (KeyCode::ArrowDown, 0x8)];
+                keymap.physical.extend(arrows);
+            }
+            _ => {
+                return None;
+            }
+        }
+        Some(keymap)
+    }
+
+    /// Parses a keymap file
+    ///
+    /// ```text
+    /// # start from a preset (default: none)
+    /// preset = azerty
+    /// # then bind CHIP-8 keys, replacing the preset's keys for them
+    /// 5 = KeyW, ArrowUp, z
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut keymap = Keymap::default();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {message}", i + 1);
+            let line = match line.find('#') {
+                Some(i) => &line[..i],
+                None => line,
+            }.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected 'KEY = KEYBOARD KEYS'".to_string()))?;
+            let (name, value) = (name.trim(), value.trim());
+            if name == "preset" {
+                keymap = Keymap::preset(value).ok_or_else(|| {
+                    error(format!("unknown preset '{value}', expected one of: {}", PRESETS.join(", ")))
+                })?;
+                continue;
+            }
+
+            let key = usize::from_str_radix(name, 16)
+                .ok()
+                .filter(|key| *key < 16)
+                .ok_or_else(|| error(format!("unknown CHIP-8 key '{name}', expected 0-F")))?;
+            keymap.physical.retain(|_, bound| *bound != key);
+            keymap.logical.retain(|_, bound| *bound != key);
+            for binding in value.split(',').map(str::trim).filter(|binding| !binding.is_empty()) {
+                if let Some(code) = KEY_CODES.iter().find(|code| format!("{code:?}") == binding) {
+                    keymap.physical.insert(*code, key);
+                } else if binding.chars().count() == 1 {
+                    keymap.logical.insert(binding.to_lowercase(), key);
+                } else {
+                    return Err(error(format!("unknown keyboard key '{binding}'")));
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// Keymap from `--keymap` (a preset or a file), or else from `keymap.conf`
+    /// in the config directory, or else the physical preset
+    pub fn load(arg: Option<&str>) -> Result<Self, String> {
+        let path = match arg {
+            Some(name) if PRESETS.contains(&name) => {
+                return Ok(Keymap::preset(name).unwrap_or_default());
+            }
+            Some(path) => PathBuf::from(path),
+            None => {
+                match saves::config_dir().map(|dir| dir.join("keymap.conf")) {
+                    Some(path) if path.exists() => path,
+                    _ => {
+                        return Ok(Keymap::preset("physical").unwrap_or_default());
+                    }
+                }
+            }
+        };
+        let text = fs::read_to_string(&path).map_err(|why| format!("{}: {why}", path.display()))?;
+        println_debug!("Loaded keymap from {}", path.display());
+        Keymap::parse(&text).map_err(|why| format!("{}: {why}", path.display()))
+    }
+
+    /// CHIP-8 key pressed by a keyboard event, if any
+    pub fn key(&self, event: &KeyEvent) -> Option<usize> {
+        if let PhysicalKey::Code(code) = event.physical_key {
+            if let Some(key) = self.physical.get(&code) {
+                return Some(*key);
+            }
+        }
+        match &event.logical_key {
+            Key::Character(c) => self.logical.get(&c.to_lowercase()).copied(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_cover_the_keypad() {
+        for name in PRESETS {
+            let keymap = Keymap::preset(name).unwrap();
+            for key in 0..16 {
+                let bound = keymap.physical.values().chain(keymap.logical.values()).any(|bound| *bound == key);
+                assert!(bound, "{name} leaves out key {key:X}");
+            }
+        }
+        assert!(Keymap::preset("dvorak").is_none());
+    }
+
+    #[test]
+    fn parse_bindings() {
+        let keymap = Keymap::parse("\
+            # arrows and WASD
+            5 = ArrowUp, KeyW
+            8 = ArrowDown, S   # by character
+
+            a = Space
+        ").unwrap();
+        assert_eq!(keymap.physical[&KeyCode::ArrowUp], 0x5);
+        assert_eq!(keymap.physical[&KeyCode::KeyW], 0x5);
+        assert_eq!(keymap.physical[&KeyCode::ArrowDown], 0x8);
+        assert_eq!(keymap.logical["s"], 0x8);
+        assert_eq!(keymap.physical[&KeyCode::Space], 0xa);
+        assert_eq!(keymap.physical.len() + keymap.logical.len(), 5);
+    }
+
+    #[test]
+    fn parse_replaces_preset_keys() {
+        let keymap = Keymap::parse("preset = azerty\n5 = ArrowUp\n").unwrap();
+        let azerty = Keymap::preset("azerty").unwrap();
+        // 5 was z
+        assert_eq!(azerty.logical["z"], 0x5);
+        assert!(!keymap.logical.contains_key("z"));
+        assert_eq!(keymap.physical[&KeyCode::ArrowUp], 0x5);
+        assert_eq!(keymap.logical["a"], 0x4);
+        assert_eq!(keymap.physical[&KeyCode::Digit1], 0x1);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let errors = [
+            ("5 ArrowUp", "line 1: expected 'KEY = KEYBOARD KEYS'"),
+            ("\npreset = dvorak", "line 2: unknown preset 'dvorak', expected one of: physical, azerty, qwertz, numpad"),
+            ("10 = KeyA", "line 1: unknown CHIP-8 key '10', expected 0-F"),
+            ("5 = KeyW, Up", "line 1: unknown keyboard key 'Up'"),
+        ];
+        for (text, message) in errors {
+            assert_eq!(Keymap::parse(text).unwrap_err(), message);
+        }
+    }
+}
@@ -1,49 +1,119 @@
 #![allow(non_snake_case)]
 
-use std::fs::File;
-use std::io::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
 use std::ops::{ BitAndAssign, BitOrAssign, BitXorAssign };
 use std::path::Path;
-use std::sync::{ Arc, Mutex };
-use std::time::{ Instant, Duration };
-use std::thread;
-use pixels::{ Pixels, wgpu::Color };
-use rand::Rng;
-use rodio::{ OutputStream, Sink };
-use rodio::source::{ SineWave, Source };
 
+mod asm;
+mod capture;
+mod coverage;
+mod disasm;
+mod error;
 mod font;
-mod display;
+mod image;
+mod instruction;
+mod movie;
+mod observer;
+mod profile;
+mod quirks;
+mod rewind;
+mod rng;
+mod state;
+mod trace;
+mod watch;
 
-use font::FONT;
+pub use asm::{ assemble, AsmError, Assembly };
+pub use capture::{ Capture, CaptureFormat };
+pub use coverage::Coverage;
+pub use disasm::{ disassemble, disassemble_linear, DisasmLine };
+pub use error::Chip8Error;
+pub use image::{ framebuffer_hash, framebuffer_to_pbm, framebuffer_to_png, framebuffer_to_text };
+pub use instruction::Instruction;
+pub use movie::Movie;
+pub use observer::Observer;
+pub use profile::Profile;
+pub use quirks::{ Platform, Quirks };
+pub use rewind::Rewind;
+pub use trace::Trace;
+pub use watch::{ Access, MemoryAccess, Watchpoint, WatchHit };
+use font::{ BIG_FONT, FONT };
+use rng::Rng;
 
-const MEM_SIZE: usize = 4096; // bytes
-const N_REGISTERS: usize = 16;
+pub const MEM_SIZE: usize = 65536; // bytes (XO-CHIP sized)
+pub const N_REGISTERS: usize = 16;
+pub const STACK_SIZE: usize = 16; // levels of nesting
 
 const PROGRAM_START_ADDR: u16 = 0x200;
 const FONT_ADDR: u16 = 0x050;
+const BIG_FONT_ADDR: u16 = 0x0a0;
+const RPL_FLAGS: usize = 16;
+const AUDIO_PATTERN_SIZE: usize = 16; // bytes
+const DEFAULT_PITCH: u8 = 64;
 
+/// Low resolution screen size
 pub const SCREEN_W: usize = 64;
 pub const SCREEN_H: usize = 32;
-const REFRESH_RATE: usize = 60; // hz
-const ON_COLOR: [u8; 4] = [0xcd, 0xda, 0xff, 0xff];
-const OFF_COLOR: [u8; 4] = [0x00, 0x0c, 0x1c, 0xff];
+/// High resolution (SUPER-CHIP) screen size
+pub const HIRES_SCREEN_W: usize = 128;
+pub const HIRES_SCREEN_H: usize = 64;
+pub const REFRESH_RATE: usize = 60; // hz
 
 pub const BUZZER_FREQ: f32 = 1000.0; // hz
 
 pub static mut DEBUG_ENABLED: bool = false;
 
+/// Framebuffer, indexed `[y][x]`
+///
+/// Each pixel is a bitmask of the (XO-CHIP) planes that are lit, so it's 0-3:
+/// plain CHIP-8 only ever uses the first plane.
+///
+/// Sized for high resolution mode, in low resolution mode only the top left
+/// `SCREEN_W` x `SCREEN_H` pixels are used.
+pub type PixelBuf = [[u8; HIRES_SCREEN_W]; HIRES_SCREEN_H];
+
+/// Presents the emulator's framebuffer to the user (window, terminal, etc.)
+///
+/// The core never draws anything itself, it only hands `pixel_buf` over to
+/// whichever frontend is driving it.
+pub trait Frontend {
+    /// Called whenever the framebuffer has changed and should be shown
+    fn render(&mut self, pixel_buf: &PixelBuf, hires: bool);
+}
+
+/// What happened while executing one or more instructions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    /// The framebuffer changed and should be rendered
+    pub drew: bool,
+    /// The sound timer is running, so the buzzer should be audible
+    pub beeping: bool,
+    /// Execution is blocked on FX0A until a key is released
+    pub waiting_for_key: bool,
+    /// Execution is blocked on DXYN until the next 60hz tick (display wait quirk)
+    pub waiting_for_display: bool,
+    /// The program exited (00FD), nothing more will be executed
+    pub exited: bool,
+    /// Execution arrived at a breakpoint
+    pub breakpoint: bool,
+    /// A pausing watchpoint was hit, see [`Chip8::take_watch_hits`]
+    pub watchpoint: bool,
+}
+
+#[derive(Clone)]
 pub struct Chip8 {
     /// Instructions per second
     ips: usize,
-    /// Memory (4KB)
+    /// Memory (64KB, programs not written for XO-CHIP only use the first 4KB)
     memory: [u8; MEM_SIZE],
     /// Pixel buffer
-    pixel_buf: [[bool; SCREEN_W]; SCREEN_H],
+    pixel_buf: PixelBuf,
     /// Pixel buffer updated flag (used to optimize rendering)
     pixel_buf_updated: bool,
-    /// Pixels object (used for rendering)
-    pixels: Pixels,
+    /// 128x64 mode (SUPER-CHIP)
+    hires: bool,
+    /// Bitmask of the planes drawn to (XO-CHIP)
+    planes: u8,
     /// Program counter
     pc: u16,
     /// Index register
@@ -56,467 +126,1158 @@ pub struct Chip8 {
     sound_t: u8,
     /// Registers V0-VF
     V: [u8; N_REGISTERS],
+    /// Current state of the 16 key hex keypad
+    keypad: [bool; 16],
+    /// Bitmask of keys released since FX0A started waiting
+    key_releases: u16,
+    /// Blocked on FX0A
+    waiting_for_key: bool,
+    /// Instructions left over from previous frames when `ips` doesn't divide evenly
+    cycle_carry: usize,
+    /// Interpreter specific behaviours
+    quirks: Quirks,
+    /// A 60hz tick happened since the last draw (for the display wait quirk)
+    vblank: bool,
+    /// Blocked on DXYN until the next tick
+    waiting_for_display: bool,
+    /// RPL user flags (SUPER-CHIP)
+    rpl: [u8; RPL_FLAGS],
+    /// Stopped by 00FD
+    exited: bool,
+    /// Seed the random number generator started from
+    seed: u64,
+    /// Random number generator for CXNN
+    rng: Rng,
+    /// Hash of the loaded ROM, see [`rom_hash`]
+    rom_hash: u64,
+    /// Addresses that stop execution when the PC arrives at them
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    /// Watchpoint hits not yet taken by the frontend
+    watch_hits: Vec<WatchHit>,
+    /// A pausing watchpoint was hit by the current instruction
+    watch_paused: bool,
+    /// Memory read and written by the current instruction
+    accesses: Vec<MemoryAccess>,
+    /// 1-bit audio samples played while the sound timer runs (XO-CHIP)
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    /// Playback rate of the audio pattern (XO-CHIP)
+    pitch: u8,
 }
 
 impl Chip8 {
-    pub fn new(ips: usize, pixels: Pixels) -> Self {
+    /// Creates an emulator with a random seed, see [`Chip8::set_seed`]
+    pub fn new(ips: usize) -> Self {
         println_debug!("Initializing emulator");
         let mut memory = [0; MEM_SIZE];
+        let seed = rand::random();
 
         // loading font to memory
         println_debug!("Loading font");
-        for i in 0..FONT.len() {
-            memory[addr!(FONT_ADDR) + i] = FONT[i];
-        }
+        memory[FONT_ADDR as usize..FONT_ADDR as usize + FONT.len()].copy_from_slice(&FONT);
+        memory[BIG_FONT_ADDR as usize..BIG_FONT_ADDR as usize + BIG_FONT.len()].copy_from_slice(
+            &BIG_FONT
+        );
 
         Self {
             ips,
             memory,
-            pixel_buf: [[false; SCREEN_W]; SCREEN_H],
+            pixel_buf: [[0; HIRES_SCREEN_W]; HIRES_SCREEN_H],
             pixel_buf_updated: false,
-            pixels,
+            hires: false,
+            planes: 0x1,
             pc: PROGRAM_START_ADDR,
             I: 0x0,
             stack: Vec::new(),
             delay_t: 0,
             sound_t: 0,
             V: [0; N_REGISTERS],
+            keypad: [false; 16],
+            key_releases: 0,
+            waiting_for_key: false,
+            cycle_carry: 0,
+            quirks: Quirks::default(),
+            vblank: true,
+            waiting_for_display: false,
+            rpl: [0; RPL_FLAGS],
+            exited: false,
+            seed,
+            rng: Rng::new(seed),
+            rom_hash: rom_hash(&[]),
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            watch_paused: false,
+            accesses: Vec::new(),
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
         }
     }
 
-    pub fn load_rom(&mut self, path_str: &String) -> Result<(), ()> {
+    pub fn load_rom(&mut self, path_str: &str) -> Result<(), Chip8Error> {
         println_debug!("Loading ROM");
 
-        let rom_path = Path::new(path_str.as_str());
+        let rom_path = Path::new(path_str);
         println_debug!(" - Path: {}", rom_path.display());
 
-        let mut file: File = match File::open(rom_path) {
+        let rom = fs::read(rom_path).map_err(|why| {
+            println_debug!(" - Failed to open file {}: {}", rom_path.display(), why);
+            why
+        })?;
+        self.load_rom_bytes(&rom)?;
+        println_debug!(" - Read {} bytes", rom.len());
+        Ok(())
+    }
+
+    /// Copies a ROM image into memory at the program start address
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let start = PROGRAM_START_ADDR as usize;
+        let max = MEM_SIZE - start;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: rom.len(), max });
+        }
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        self.rom_hash = rom_hash(rom);
+        Ok(())
+    }
+
+    /// Hash of the loaded ROM, identifying it for save files
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Current contents of the framebuffer
+    pub fn pixel_buf(&self) -> &PixelBuf {
+        &self.pixel_buf
+    }
+
+    /// Whether the screen is in 128x64 mode
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Current screen size in pixels
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.hires { (HIRES_SCREEN_W, HIRES_SCREEN_H) } else { (SCREEN_W, SCREEN_H) }
+    }
+
+    /// RPL user flags, which SUPER-CHIP programs use as persistent storage
+    pub fn rpl_flags(&self) -> &[u8; RPL_FLAGS] {
+        &self.rpl
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; RPL_FLAGS]) {
+        self.rpl = flags;
+    }
+
+    /// Audio pattern loaded by F002, if any (XO-CHIP)
+    ///
+    /// 128 1-bit samples, most significant bit first. Without a pattern the
+    /// buzzer is just a plain tone at `BUZZER_FREQ`.
+    pub fn audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    /// Sample rate the audio pattern should be played back at (XO-CHIP)
+    pub fn audio_sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf(((self.pitch as f32) - 64.0) / 48.0)
+    }
+
+    /// Seed the random number generator was last (re)started from
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restarts the random number generator used by CXNN from `seed`, so
+    /// runs with the same seed and input play out identically
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+    }
+
+    /// Program counter
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Index register
+    pub fn index(&self) -> u16 {
+        self.I
+    }
+
+    /// Registers V0-VF
+    pub fn registers(&self) -> &[u8; N_REGISTERS] {
+        &self.V
+    }
+
+    /// Return addresses of the subroutines being executed, innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_t
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_t
+    }
+
+    /// The whole address space
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn set_pc(&mut self, addr: u16) {
+        self.pc = addr;
+    }
+
+    pub fn set_index(&mut self, addr: u16) {
+        self.I = addr;
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.V[x] = value;
+    }
+
+    /// Pops return addresses off the stack, or pushes `0x000`, until it holds
+    /// `depth` of them (at most [`STACK_SIZE`])
+    pub fn set_stack_depth(&mut self, depth: usize) {
+        self.stack.resize(depth.min(STACK_SIZE), 0);
+    }
+
+    /// Copies `bytes` into memory from `addr` on, wrapping around at the end
+    pub fn write_memory(&mut self, addr: u16, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.memory[(addr as usize + i) % MEM_SIZE] = *byte;
+        }
+    }
+
+    /// Stops execution when the PC arrives at `addr`, see [`StepResult::breakpoint`]
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Returns whether there was a breakpoint at `addr`
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Breakpoint addresses, in order
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Instructions per second
+    pub fn ips(&self) -> usize {
+        self.ips
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Updates the keypad, keeping track of releases for FX0A
+    pub fn set_keypad(&mut self, keypad: [bool; 16]) {
+        for (i, (&was_down, &is_down)) in self.keypad.iter().zip(keypad.iter()).enumerate() {
+            if was_down && !is_down {
+                self.key_releases |= 1 << i;
+            }
+        }
+        self.keypad = keypad;
+    }
+
+    /// Fetches and executes a single instruction
+    ///
+    /// Timers are left alone, see [`Chip8::tick_timers`].
+    pub fn step(&mut self) -> Result<StepResult, Chip8Error> {
+        if self.exited {
+            return Ok(StepResult { exited: true, ..Default::default() });
+        }
+
+        let pc = self.pc;
+        self.watch_paused = false;
+        self.accesses.clear();
+        // checked on fetching, so the first instruction and wherever a
+        // resume or state load lands count too, but not an instruction
+        // that's waiting in place (FX0A) again
+        if !self.waiting_for_key && !self.waiting_for_display {
+            let size = if self.read_word(pc) == 0xf000 { 4 } else { 2 };
+            self.watch(pc, Access::Execute, pc as usize, size);
+        }
+        let instruction = self.fetch_instruction();
+        if let Err(why) = self.decode_and_execute(pc, instruction) {
+            // leaving the machine pointing at the faulting instruction
+            self.pc = pc;
+            return Err(why);
+        }
+
+        let drew = self.pixel_buf_updated;
+        self.pixel_buf_updated = false;
+        Ok(StepResult {
+            drew,
+            beeping: self.sound_t > 0,
+            waiting_for_key: self.waiting_for_key,
+            waiting_for_display: self.waiting_for_display,
+            exited: self.exited,
+            // instructions that wait in place (FX0A) don't count as arriving
+            breakpoint: !self.waiting_for_key
+                && !self.waiting_for_display
+                && self.breakpoints.contains(&self.pc),
+            watchpoint: self.watch_paused,
+        })
+    }
+
+    /// Executes one instruction, letting `observer` look on
+    pub fn step_observed(&mut self, observer: &mut impl Observer) -> Result<StepResult, Chip8Error> {
+        if self.exited {
+            return self.step();
+        }
+        observer.before_step(self);
+        let result = match self.step() {
+            Ok(result) => result,
             Err(why) => {
-                println_debug!(" - Failed to open file {}: {}", rom_path.display(), why);
-                return Err(());
+                observer.step_failed(self, &why);
+                return Err(why);
             }
-            Ok(file) => file,
         };
+        observer.after_step(self, &result);
+        Ok(result)
+    }
+
+    /// Executes up to `n` instructions, stopping early if blocked on a keypress
+    /// or the display, or at a breakpoint or watchpoint
+    pub fn run_cycles(&mut self, n: usize) -> Result<StepResult, Chip8Error> {
+        self.run_cycles_observed(n, &mut ())
+    }
 
-        let mut addr = addr!(PROGRAM_START_ADDR);
-        let mut total_bytes = 0;
-        loop {
-            let bytes_read = file
-                .read(&mut self.memory[addr..addr + 1])
-                .expect(" - Failed to read ");
-            if bytes_read == 0 {
+    /// [`Chip8::run_cycles`], letting `observer` look on
+    pub fn run_cycles_observed(&mut self, n: usize, observer: &mut impl Observer) -> Result<StepResult, Chip8Error> {
+        let mut result = StepResult {
+            beeping: self.sound_t > 0,
+            waiting_for_key: self.waiting_for_key,
+            waiting_for_display: self.waiting_for_display,
+            exited: self.exited,
+            ..Default::default()
+        };
+        for _ in 0..n {
+            let step = self.step_observed(observer)?;
+            result.drew |= step.drew;
+            result.beeping = step.beeping;
+            result.waiting_for_key = step.waiting_for_key;
+            result.waiting_for_display = step.waiting_for_display;
+            result.exited = step.exited;
+            result.breakpoint = step.breakpoint;
+            result.watchpoint = step.watchpoint;
+            if step.waiting_for_key || step.waiting_for_display || step.exited || step.breakpoint || step.watchpoint {
                 break;
             }
-            total_bytes += bytes_read;
-            addr += 1;
         }
-        println_debug!(" - Read {} bytes", total_bytes);
-        Ok(())
+        Ok(result)
     }
 
-    /// Starts execution cycle
-    pub fn run(&mut self, keypad_state: Arc<Mutex<[bool; 16]>>) -> () {
-        // configuring pixel buffer
-        self.pixels.clear_color(Color {
-            r: OFF_COLOR[0] as f64,
-            g: OFF_COLOR[1] as f64,
-            b: OFF_COLOR[2] as f64,
-            a: OFF_COLOR[3] as f64,
-        });
-        self.render();
-
-        // configuring buzzer
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let buzzer = Sink::try_new(&stream_handle).unwrap();
-        buzzer.pause();
-        buzzer.append(SineWave::new(BUZZER_FREQ).amplify(0.1).repeat_infinite());
-
-        // execution loop
-        println_debug!("Starting execution\n");
-        let time_per_instruction = Duration::from_secs_f64(1.0 / (self.ips as f64));
-        let time_per_tick = Duration::from_secs_f64(1.0 / (REFRESH_RATE as f64));
-        let mut last_tick_time = Instant::now();
-        loop {
-            let start_time = Instant::now();
-
-            // CPU logic
-            let instruction = self.fetch_instruction();
-            //println_debug!("{:#05X} > {:#06X}", self.pc - 2, instruction);
-
-            match self.decode_and_execute(instruction, &keypad_state) {
-                Ok(_) => {}
-                Err(why) => {
-                    println!("Failed: {why}");
-                    break;
-                }
-            }
+    /// Executes one 60hz frame worth of instructions (`ips / 60`), then ticks the timers
+    pub fn run_frame(&mut self) -> Result<StepResult, Chip8Error> {
+        self.run_frame_observed(&mut ())
+    }
 
-            // 60hz tick
-            if Instant::now() - last_tick_time > time_per_tick {
-                if self.pixel_buf_updated {
-                    self.render();
-                    self.pixel_buf_updated = false;
-                }
+    /// [`Chip8::run_frame`], letting `observer` look on
+    pub fn run_frame_observed(&mut self, observer: &mut impl Observer) -> Result<StepResult, Chip8Error> {
+        let cycles = (self.ips + self.cycle_carry) / REFRESH_RATE;
+        self.cycle_carry = (self.ips + self.cycle_carry) % REFRESH_RATE;
 
-                // delay timer
-                if self.delay_t > 0 {
-                    self.delay_t -= 1;
-                }
-                // sound timer
-                if self.sound_t > 0 {
-                    if buzzer.is_paused() {
-                        buzzer.play();
-                    }
-                    self.sound_t -= 1;
-                } else if !buzzer.is_paused() {
-                    buzzer.pause();
-                }
+        let mut result = self.run_cycles_observed(cycles, observer)?;
+        result.beeping = self.tick_timers();
+        Ok(result)
+    }
 
-                last_tick_time = Instant::now();
-            }
+    /// Counts down the delay and sound timers, should be called at 60hz
+    ///
+    /// Returns whether the buzzer should be sounding.
+    pub fn tick_timers(&mut self) -> bool {
+        self.vblank = true;
 
-            let elapsed = Instant::now() - start_time;
-            if elapsed < time_per_instruction {
-                thread::sleep(time_per_instruction - elapsed);
-            }
+        // delay timer
+        if self.delay_t > 0 {
+            self.delay_t -= 1;
+        }
+        // sound timer
+        if self.sound_t > 0 {
+            self.sound_t -= 1;
+            true
+        } else {
+            false
         }
-        println_debug!("Completed execution");
     }
 
     fn fetch_instruction(&mut self) -> u16 {
-        let instruction =
-            ((self.memory[addr!(self.pc)] as u16) << 8) | (self.memory[addr!(self.pc + 1)] as u16);
+        let instruction = self.read_word(self.pc);
         self.pc = self.pc.wrapping_add(2);
         instruction
     }
 
-    fn decode_and_execute(
-        &mut self,
-        instruction: u16,
-        keypad_state: &Arc<Mutex<[bool; 16]>>
-    ) -> Result<(), &str> {
-        // helper closure to simplify extracting key value from arc mutex
-        let get_keypad_state = || {
-            let keypad: [bool; 16] = *keypad_state.lock().unwrap();
-            keypad
-        };
+    fn read_word(&self, addr: u16) -> u16 {
+        u16::from_be_bytes([self.memory[addr as usize], self.memory[addr.wrapping_add(1) as usize]])
+    }
 
-        // deconstructing instruction
-        let nibbles: [u16; 4] = [
-            (instruction & 0xf000).checked_shr(12).unwrap(),
-            (instruction & 0x0f00).checked_shr(8).unwrap(),
-            (instruction & 0x00f0).checked_shr(4).unwrap(),
-            instruction & 0x000f,
-        ];
+    /// Skips the next instruction, which takes 4 bytes if it's `F000 NNNN`
+    fn skip(&mut self) {
+        let size = if self.read_word(self.pc) == 0xf000 { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(size);
+    }
 
-        // Register identifiers
-        let X = nibbles[1] as usize;
-        let Y = nibbles[2] as usize;
+    fn decode_and_execute(&mut self, pc: u16, opcode: u16) -> Result<(), Chip8Error> {
+        let unknown = Chip8Error::UnknownOpcode { pc, opcode };
+        let out_of_bounds = |addr: usize| Chip8Error::MemoryOutOfBounds { pc, opcode, addr };
 
-        // Values/constants
-        let N = (instruction & 0x000f) as u8;
-        let NN = (instruction & 0x00ff) as u8;
-        let NNN = instruction & 0x0fff;
+        let instruction = match Instruction::decode(opcode) {
+            Some(instruction) => instruction,
+            None => {
+                return Err(unknown);
+            }
+        };
 
         // Instruction handling
-        match nibbles[0] {
-            0x0 => {
-                match nibbles[1] {
-                    0x0 => {
-                        match (nibbles[2], nibbles[3]) {
-                            (0xe, 0x0) => {
-                                // Clear screen
-                                self.pixel_buf = [[false; SCREEN_W]; SCREEN_H];
-                                self.pixel_buf_updated = true;
-                            }
-                            (0xe, 0xe) => {
-                                // Return from subroutine
-                                self.pc = match self.stack.pop() {
-                                    Some(pc) => pc,
-                                    None => {
-                                        return Err("Returned outside of subroutine");
-                                    }
-                                };
-                            }
-                            _ => {
-                                return Err("Unknown instruction");
-                            }
-                        }
-                    }
-                    _ => {
-                        // Call machine code routine
-                        return Err("Unhandled instruction");
+        match instruction {
+            Instruction::Sys(_) => {
+                // Call machine code routine
+                return Err(unknown);
+            }
+            Instruction::Cls => {
+                // Clear screen
+                self.clear_screen();
+            }
+            Instruction::Ret => {
+                // Return from subroutine
+                self.pc = match self.stack.pop() {
+                    Some(pc) => pc,
+                    None => {
+                        return Err(Chip8Error::StackUnderflow { pc, opcode });
                     }
-                }
+                };
+            }
+            Instruction::Scd(N) => {
+                // Scroll down N pixels
+                self.scroll(0, N as isize);
+            }
+            Instruction::Scu(N) => {
+                // Scroll up N pixels
+                self.scroll(0, -(N as isize));
+            }
+            Instruction::Scr => {
+                // Scroll right 4 pixels
+                self.scroll(4, 0);
+            }
+            Instruction::Scl => {
+                // Scroll left 4 pixels
+                self.scroll(-4, 0);
             }
-            0x1 => {
+            Instruction::Exit => {
+                // Exit interpreter
+                self.exited = true;
+            }
+            Instruction::Low => {
+                // Switch to low resolution (clears the screen)
+                self.hires = false;
+                self.clear_screen();
+            }
+            Instruction::High => {
+                // Switch to high resolution (clears the screen)
+                self.hires = true;
+                self.clear_screen();
+            }
+            Instruction::Jp(NNN) => {
                 // Jump
                 self.pc = NNN;
             }
-            0x2 => {
+            Instruction::Call(NNN) => {
                 // Call subroutine at NNN
+                if self.stack.len() >= STACK_SIZE {
+                    return Err(Chip8Error::StackOverflow { pc, opcode });
+                }
                 self.stack.push(self.pc);
                 self.pc = NNN;
             }
-            0x3 => {
+            Instruction::SeImm(X, NN) => {
                 // Skip if VX == NN
-                if self.V[X] == NN {
-                    self.pc += 2;
+                if self.V[X as usize] == NN {
+                    self.skip();
                 }
             }
-            0x4 => {
+            Instruction::SaveRange(X, Y) => {
+                // Store VX, ... VY at address I+0, ... (I unchanged)
+                // (goes backwards if X > Y)
+                let (X, Y) = (X as usize, Y as usize);
+                let (addr, len) = (self.I as usize, X.abs_diff(Y) + 1);
+                if addr + len > MEM_SIZE {
+                    return Err(out_of_bounds(addr + len - 1));
+                }
+                self.access(pc, Access::Write, addr, len);
+                for i in 0..len {
+                    let reg = if X <= Y { X + i } else { X - i };
+                    self.memory[addr + i] = self.V[reg];
+                }
+            }
+            Instruction::LoadRange(X, Y) => {
+                // Load VX, ... VY from address I+0, ... (I unchanged)
+                // (goes backwards if X > Y)
+                let (X, Y) = (X as usize, Y as usize);
+                let (addr, len) = (self.I as usize, X.abs_diff(Y) + 1);
+                if addr + len > MEM_SIZE {
+                    return Err(out_of_bounds(addr + len - 1));
+                }
+                self.access(pc, Access::Read, addr, len);
+                for i in 0..len {
+                    let reg = if X <= Y { X + i } else { X - i };
+                    self.V[reg] = self.memory[addr + i];
+                }
+            }
+            Instruction::SneImm(X, NN) => {
                 // Skip if VX != NN
-                if self.V[X] != NN {
-                    self.pc += 2;
+                if self.V[X as usize] != NN {
+                    self.skip();
                 }
             }
-            0x5 => {
+            Instruction::SeReg(X, Y) => {
                 // Skip if VX == VY
-                if self.V[X] == self.V[Y] {
-                    self.pc += 2;
+                if self.V[X as usize] == self.V[Y as usize] {
+                    self.skip();
                 }
             }
-            0x6 => {
+            Instruction::LdImm(X, NN) => {
                 // Set register to value
-                self.V[X] = NN;
+                self.V[X as usize] = NN;
             }
-            0x7 => {
+            Instruction::AddImm(X, NN) => {
                 // Add value to register
-                self.V[X] = self.V[X].wrapping_add(NN);
+                self.V[X as usize] = self.V[X as usize].wrapping_add(NN);
             }
-            0x8 => {
-                match nibbles[3] {
-                    0x0 => {
-                        // Assign VX = VY
-                        self.V[X] = self.V[Y];
-                    }
-                    0x1 => {
-                        // Assign VX |= VY (bitwise or)
-                        self.V[X].bitor_assign(self.V[Y]);
-                    }
-                    0x2 => {
-                        // Assign VX &= VY (bitwise and)
-                        self.V[X].bitand_assign(self.V[Y]);
-                    }
-                    0x3 => {
-                        // Assign VX ^= VY (bitwise xor)
-                        self.V[X].bitxor_assign(self.V[Y]);
-                    }
-                    0x4 => {
-                        // Assign VX += VY
-                        self.V[X] = self.V[X].wrapping_add(self.V[Y]);
-                    }
-                    0x5 => {
-                        // Assign VX -= VY
-                        let underflow: bool;
-                        (self.V[X], underflow) = self.V[X].overflowing_sub(self.V[Y]);
-                        self.V[0xf] = if underflow { 0 } else { 1 };
-                    }
-                    0x6 => {
-                        // Bitshift right VX >>= 1
-                        // (store overflow in VF)
-                        self.V[0xf] = self.V[X] & 0x1;
-                        self.V[X] = self.V[X].wrapping_shr(1);
-                        self.V[X] &= 0x7f;
-                    }
-                    0x7 => {
-                        // Assign VX = VY - VX
-                        // (set VF to 0 if underflow, 1 otherwise)
-                        self.V[0xf] = if self.V[X] <= self.V[Y] { 1 } else { 0 };
-                        self.V[X] = self.V[Y] - self.V[X];
-                    }
-                    0xe => {
-                        // Bitshift left VX <<= 1
-                        // (store overflow in VF)
-                        self.V[X] = self.V[X].wrapping_shl(1);
-                        self.V[0xf] = self.V[X] & 0x1;
-                        self.V[X] &= 0xfe;
-                    }
-                    _ => {
-                        return Err("Unknown instruction");
-                    }
+            Instruction::LdReg(X, Y) => {
+                // Assign VX = VY
+                self.V[X as usize] = self.V[Y as usize];
+            }
+            Instruction::Or(X, Y) => {
+                // Assign VX |= VY (bitwise or)
+                self.V[X as usize].bitor_assign(self.V[Y as usize]);
+                if self.quirks.vf_reset {
+                    self.V[0xf] = 0;
                 }
             }
-            0x9 => {
+            Instruction::And(X, Y) => {
+                // Assign VX &= VY (bitwise and)
+                self.V[X as usize].bitand_assign(self.V[Y as usize]);
+                if self.quirks.vf_reset {
+                    self.V[0xf] = 0;
+                }
+            }
+            Instruction::Xor(X, Y) => {
+                // Assign VX ^= VY (bitwise xor)
+                self.V[X as usize].bitxor_assign(self.V[Y as usize]);
+                if self.quirks.vf_reset {
+                    self.V[0xf] = 0;
+                }
+            }
+            // (flags are written after the result, so VF as an operand gets overwritten)
+            Instruction::AddReg(X, Y) => {
+                // Assign VX += VY
+                // (set VF to 1 if overflow, 0 otherwise)
+                let (X, Y) = (X as usize, Y as usize);
+                let overflow: bool;
+                (self.V[X], overflow) = self.V[X].overflowing_add(self.V[Y]);
+                self.V[0xf] = if overflow { 1 } else { 0 };
+            }
+            Instruction::Sub(X, Y) => {
+                // Assign VX -= VY
+                // (set VF to 0 if underflow, 1 otherwise)
+                let (X, Y) = (X as usize, Y as usize);
+                let underflow: bool;
+                (self.V[X], underflow) = self.V[X].overflowing_sub(self.V[Y]);
+                self.V[0xf] = if underflow { 0 } else { 1 };
+            }
+            Instruction::Shr(X, Y) => {
+                // Bitshift right VX >>= 1 (or VX = VY >> 1)
+                // (store shifted out bit in VF)
+                let source = if self.quirks.shift_vy { self.V[Y as usize] } else { self.V[X as usize] };
+                self.V[X as usize] = source >> 1;
+                self.V[0xf] = source & 0x1;
+            }
+            Instruction::Subn(X, Y) => {
+                // Assign VX = VY - VX
+                // (set VF to 0 if underflow, 1 otherwise)
+                let (X, Y) = (X as usize, Y as usize);
+                let underflow: bool;
+                (self.V[X], underflow) = self.V[Y].overflowing_sub(self.V[X]);
+                self.V[0xf] = if underflow { 0 } else { 1 };
+            }
+            Instruction::Shl(X, Y) => {
+                // Bitshift left VX <<= 1 (or VX = VY << 1)
+                // (store shifted out bit in VF)
+                let source = if self.quirks.shift_vy { self.V[Y as usize] } else { self.V[X as usize] };
+                self.V[X as usize] = source << 1;
+                self.V[0xf] = source >> 7;
+            }
+            Instruction::SneReg(X, Y) => {
                 // Skip if VX != VY
-                if self.V[X] != self.V[Y] {
-                    self.pc += 2;
+                if self.V[X as usize] != self.V[Y as usize] {
+                    self.skip();
                 }
             }
-            0xa => {
+            Instruction::LdI(NNN) => {
                 // Set index register
                 self.I = NNN;
             }
-            0xb => {
-                // Jump to NNN + V0
-                self.I = NNN + (self.V[0] as u16);
+            Instruction::JpV0(NNN) => {
+                // Jump to NNN + V0 (or XNN + VX)
+                let offset = if self.quirks.jump_vx { self.V[(NNN >> 8) as usize] } else { self.V[0] };
+                self.pc = NNN + (offset as u16);
             }
-            0xc => {
+            Instruction::Rnd(X, NN) => {
                 // Rand gen
                 // Sets VX to random u8 & NN
-                self.V[X] = rand::thread_rng().gen::<u8>() & NN;
+                self.V[X as usize] = self.rng.next_u8() & NN;
             }
-            0xd => {
+            Instruction::Drw(X, Y, N) => {
                 // Draw
                 // draws an 8 wide, N tall sprite at VX, VY from the memory location at I
-                let sprite_x = self.V[X] as usize;
-                let sprite_y = self.V[Y] as usize;
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        // re-executing this instruction until the next tick
+                        self.waiting_for_display = true;
+                        self.pc = self.pc.wrapping_sub(2);
+                        return Ok(());
+                    }
+                    self.vblank = false;
+                    self.waiting_for_display = false;
+                }
+
+                // the starting position always wraps, the rest of the sprite is
+                // either wrapped or clipped
+                let (w, h) = self.resolution();
+                let sprite_x = (self.V[X as usize] as usize) % w;
+                let sprite_y = (self.V[Y as usize] as usize) % h;
+
+                // DXY0 draws a 16x16 sprite, 2 bytes per row
+                // (with multiple planes selected, each plane's sprite follows the last)
+                let (sprite_w, sprite_h) = if N == 0 { (16, 16) } else { (8, N as usize) };
+                let sprite_len = (sprite_w / 8) * sprite_h;
+                let n_planes = self.planes.count_ones() as usize;
+                let sprite_addr = self.I as usize;
+                if sprite_addr + sprite_len * n_planes > MEM_SIZE {
+                    return Err(out_of_bounds(sprite_addr + sprite_len * n_planes - 1));
+                }
+                self.access(pc, Access::Read, sprite_addr, sprite_len * n_planes);
+
                 let mut unset_pixel = false;
-                for row in 0..N as usize {
-                    let mut pixel_values = self.memory[addr!(self.I) + row];
-                    for col in (0..8).rev() {
-                        if (pixel_values & 0x1) == 1 {
-                            let old_value = self.pixel_buf[sprite_y + row][sprite_x + col];
-                            self.pixel_buf[sprite_y + row][sprite_x + col] = !old_value;
-                            if old_value == true {
+                let mut sprite = &self.memory[sprite_addr..sprite_addr + sprite_len * n_planes];
+                for plane in [0x1, 0x2] {
+                    if self.planes & plane == 0 {
+                        continue;
+                    }
+                    for row in 0..sprite_h {
+                        for col in 0..sprite_w {
+                            let byte = sprite[row * (sprite_w / 8) + col / 8];
+                            if (byte << (col % 8)) & 0x80 == 0 {
+                                continue;
+                            }
+                            let (mut x, mut y) = (sprite_x + col, sprite_y + row);
+                            if self.quirks.wrap_sprites {
+                                (x, y) = (x % w, y % h);
+                            } else if x >= w || y >= h {
+                                continue;
+                            }
+
+                            if self.pixel_buf[y][x] & plane != 0 {
                                 unset_pixel = true;
                             }
+                            self.pixel_buf[y][x] ^= plane;
                         }
-                        pixel_values >>= 1;
                     }
+                    sprite = &sprite[sprite_len..];
                 }
                 self.V[0xf] = if unset_pixel { 1 } else { 0 };
                 self.pixel_buf_updated = true;
             }
-            0xe => {
-                match (nibbles[2], nibbles[3]) {
-                    (0x9, 0xe) => {
-                        // Skip if key_pressed == VX
-                        let keypad = get_keypad_state();
-                        if keypad[self.V[X] as usize] {
-                            self.pc += 2;
-                        }
-                    }
-                    (0xa, 0x1) => {
-                        // Skip if key_pressed != VX
-                        let keypad = get_keypad_state();
-                        if !keypad[self.V[X] as usize] {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => {
-                        return Err("Unknown instruction");
-                    }
+            Instruction::Skp(X) => {
+                // Skip if key_pressed == VX
+                if self.keypad[(self.V[X as usize] & 0xf) as usize] {
+                    self.skip();
                 }
             }
-            0xf => {
-                match (nibbles[2], nibbles[3]) {
-                    (0x0, 0x7) => {
-                        // Set VX to delay timer value
-                        self.V[X] = self.delay_t;
-                    }
-                    (0x0, 0xa) => {
-                        // Block for next keypress, store in VX
-
-                        // rendering before blocking (cus blocking takes long time someimtes)
-                        self.render();
-                        self.pixel_buf_updated = false;
-
-                        // waiting for key event that is not a release
-                        let mut keypad = get_keypad_state();
-                        let mut last_keypad = keypad;
-                        let mut done = false;
-                        while !done {
-                            thread::sleep(std::time::Duration::from_secs_f32(1.0 / 60.0));
-                            for i in 0..16 {
-                                if !keypad[i] && last_keypad[i] {
-                                    self.V[X] = i as u8;
-                                    done = true;
-                                    break;
-                                }
-                            }
-                            last_keypad = keypad;
-                            keypad = get_keypad_state();
-                        }
-                    }
-                    (0x1, 0x5) => {
-                        // Set delay timer to VX
-                        self.delay_t = self.V[X];
-                    }
-                    (0x1, 0x8) => {
-                        // Set sound timer to VX
-                        self.sound_t = self.V[X];
-                    }
-                    (0x1, 0xe) => {
-                        // Increments I by VX
-                        self.I = self.I.wrapping_add(self.V[X] as u16);
-                    }
-                    (0x2, 0x9) => {
-                        // Set I to sprite location for char in VX
-                        self.I = FONT_ADDR + ((self.V[X] * 5) as u16);
-                    }
-                    (0x3, 0x3) => {
-                        // Binary coded decimal storage
-                        // Store VX's hundreds digit at I, tens at I+1, and ones at I+2
-                        self.memory[addr!(self.I)] = self.V[X].div_euclid(100);
-                        self.memory[addr!(self.I) + 1] = self.V[X].div_euclid(10) % 10;
-                        self.memory[addr!(self.I) + 2] = self.V[X] % 10;
-                    }
-                    (0x5, 0x5) => {
-                        // Register dump
-                        // Store V0, V1, ... VX at address I+0, I+1, ... I+X
-                        let addr = addr!(self.I);
-                        for i in 0..=X {
-                            self.memory[addr + i] = self.V[i];
-                        }
-                    }
-                    (0x6, 0x5) => {
-                        // Register load
-                        // Move values from I+0, I+1, ... I+X in V0, V1, ... VX
-                        let addr = addr!(self.I);
-                        for i in 0..=X {
-                            self.V[i] = self.memory[addr + i];
-                        }
-                    }
-                    _ => {
-                        return Err("Unknown instruction");
-                    }
+            Instruction::Sknp(X) => {
+                // Skip if key_pressed != VX
+                if !self.keypad[(self.V[X as usize] & 0xf) as usize] {
+                    self.skip();
+                }
+            }
+            Instruction::LdILong(_) => {
+                // Set index register to the 16 bit address in the next word
+                self.I = self.read_word(self.pc);
+                self.pc = self.pc.wrapping_add(2);
+            }
+            Instruction::Plane(N) => {
+                // Select the planes that get drawn to, cleared and scrolled
+                self.planes = N & 0x3;
+            }
+            Instruction::Audio => {
+                // Load audio pattern from I
+                let addr = self.I as usize;
+                if addr + AUDIO_PATTERN_SIZE > MEM_SIZE {
+                    return Err(out_of_bounds(addr + AUDIO_PATTERN_SIZE - 1));
+                }
+                self.access(pc, Access::Read, addr, AUDIO_PATTERN_SIZE);
+                let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                pattern.copy_from_slice(&self.memory[addr..addr + AUDIO_PATTERN_SIZE]);
+                self.audio_pattern = Some(pattern);
+            }
+            Instruction::LdVxDt(X) => {
+                // Set VX to delay timer value
+                self.V[X as usize] = self.delay_t;
+            }
+            Instruction::LdVxK(X) => {
+                // Block for next keypress, store in VX
+                // (a key counts once it has been released)
+                if !self.waiting_for_key {
+                    self.waiting_for_key = true;
+                    self.key_releases = 0;
+                }
+                if self.key_releases != 0 {
+                    self.V[X as usize] = self.key_releases.trailing_zeros() as u8;
+                    self.waiting_for_key = false;
+                } else {
+                    // re-executing this instruction until a key comes in
+                    self.pc = self.pc.wrapping_sub(2);
+                }
+            }
+            Instruction::LdDtVx(X) => {
+                // Set delay timer to VX
+                self.delay_t = self.V[X as usize];
+            }
+            Instruction::LdStVx(X) => {
+                // Set sound timer to VX
+                self.sound_t = self.V[X as usize];
+            }
+            Instruction::AddIVx(X) => {
+                // Increments I by VX
+                self.I = self.I.wrapping_add(self.V[X as usize] as u16);
+            }
+            Instruction::LdFVx(X) => {
+                // Set I to sprite location for char in VX
+                self.I = FONT_ADDR + ((self.V[X as usize] & 0xf) as u16) * 5;
+            }
+            Instruction::LdHfVx(X) => {
+                // Set I to large sprite location for char in VX
+                self.I = BIG_FONT_ADDR + ((self.V[X as usize] & 0xf) as u16) * 10;
+            }
+            Instruction::LdBVx(X) => {
+                // Binary coded decimal storage
+                // Store VX's hundreds digit at I, tens at I+1, and ones at I+2
+                let addr = self.I as usize;
+                if addr + 2 >= MEM_SIZE {
+                    return Err(out_of_bounds(addr + 2));
                 }
+                self.access(pc, Access::Write, addr, 3);
+                let value = self.V[X as usize];
+                self.memory[addr] = value.div_euclid(100);
+                self.memory[addr + 1] = value.div_euclid(10) % 10;
+                self.memory[addr + 2] = value % 10;
             }
-            _ => {
-                return Err("Unknown instruction");
+            Instruction::Pitch(X) => {
+                // Set audio pattern pitch
+                self.pitch = self.V[X as usize];
+            }
+            Instruction::LdIVx(X) => {
+                // Register dump
+                // Store V0, V1, ... VX at address I+0, I+1, ... I+X
+                let (addr, X) = (self.I as usize, X as usize);
+                if addr + X >= MEM_SIZE {
+                    return Err(out_of_bounds(addr + X));
+                }
+                self.access(pc, Access::Write, addr, X + 1);
+                self.memory[addr..=addr + X].copy_from_slice(&self.V[0..=X]);
+                if self.quirks.memory_increment {
+                    self.I = self.I.wrapping_add((X as u16) + 1);
+                }
+            }
+            Instruction::LdVxI(X) => {
+                // Register load
+                // Move values from I+0, I+1, ... I+X in V0, V1, ... VX
+                let (addr, X) = (self.I as usize, X as usize);
+                if addr + X >= MEM_SIZE {
+                    return Err(out_of_bounds(addr + X));
+                }
+                self.access(pc, Access::Read, addr, X + 1);
+                self.V[0..=X].copy_from_slice(&self.memory[addr..=addr + X]);
+                if self.quirks.memory_increment {
+                    self.I = self.I.wrapping_add((X as u16) + 1);
+                }
+            }
+            Instruction::LdRVx(X) => {
+                // Store V0, V1, ... VX in the RPL user flags
+                let X = X as usize;
+                self.rpl[0..=X].copy_from_slice(&self.V[0..=X]);
+            }
+            Instruction::LdVxR(X) => {
+                // Load V0, V1, ... VX from the RPL user flags
+                let X = X as usize;
+                self.V[0..=X].copy_from_slice(&self.rpl[0..=X]);
             }
         }
 
         Ok(())
     }
 
-    /// Applies the current display buffer (pixel_buf) to the screen
-    fn render(&mut self) {
-        for (i, pixel) in self.pixels.frame_mut().chunks_exact_mut(4).enumerate() {
-            let x = (i % (SCREEN_W as usize)) as usize;
-            let y = (i / (SCREEN_W as usize)) as usize;
-            let rgba = if self.pixel_buf[y][x] { ON_COLOR } else { OFF_COLOR };
+    /// Clears the selected planes
+    fn clear_screen(&mut self) {
+        for row in self.pixel_buf.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !self.planes;
+            }
+        }
+        self.pixel_buf_updated = true;
+    }
 
-            pixel.copy_from_slice(&rgba);
+    /// Moves the selected planes by `dx`, `dy` pixels, filling in with blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (w, h) = self.resolution();
+        let old_buf = self.pixel_buf;
+        for y in 0..h {
+            for x in 0..w {
+                let (src_x, src_y) = (x as isize - dx, y as isize - dy);
+                let in_bounds = (0..w as isize).contains(&src_x) && (0..h as isize).contains(&src_y);
+                let moved = if in_bounds { old_buf[src_y as usize][src_x as usize] & self.planes } else { 0 };
+                self.pixel_buf[y][x] = (old_buf[y][x] & !self.planes) | moved;
+            }
         }
-        self.pixels.render().unwrap();
+        self.pixel_buf_updated = true;
     }
 }
 
-/// Converting u16 addresses to usize, masking the first 12 bits
-#[macro_export]
-macro_rules! addr {
-    ($num:expr) => {
-		($num & 0x0FFF) as usize
-    };
+/// Hashes a ROM image (64 bit FNV-1a), stable across builds and platforms
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ (*byte as u64)).wrapping_mul(0x100000001b3))
 }
 
 // debugging messages that can be enabled/disabled
 #[macro_export]
 macro_rules! println_debug {
     ($msg:literal) => {
-		unsafe {
-			if DEBUG_ENABLED {
-				println!($msg);
-			}
+		if unsafe { DEBUG_ENABLED } {
+			println!($msg);
 		}
     };
     ($msg:literal, $($args:expr),*) => {
-		unsafe {
-			if DEBUG_ENABLED {
-				println!($msg, $($args),*);
-			}
+		if unsafe { DEBUG_ENABLED } {
+			println!($msg, $($args),*);
 		}
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Emulator with `rom` loaded at the program start address, for the tests
+    /// of every module
+    pub(crate) fn boot(ips: usize, rom: &[u8]) -> Chip8 {
+        let mut emulator = Chip8::new(ips);
+        let start = PROGRAM_START_ADDR as usize;
+        emulator.memory[start..start + rom.len()].copy_from_slice(rom);
+        emulator
+    }
+
+    /// Emulator with an example ROM loaded and a fixed seed, for the tests of
+    /// every module
+    pub(crate) fn example(name: &str) -> Chip8 {
+        let mut emulator = Chip8::new(700);
+        emulator.set_seed(1);
+        let path = format!("{}/examples/{name}.ch8", env!("CARGO_MANIFEST_DIR"));
+        emulator.load_rom(&path).unwrap();
+        emulator
+    }
+
+    /// ADD V0, 1 then JP 0x200, forever
+    const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn step_executes_one_instruction() {
+        let mut emulator = boot(700, &[0x6a, 0x05, 0x7a, 0x03]);
+        emulator.step().unwrap();
+        assert_eq!((emulator.V[0xa], emulator.pc), (0x05, 0x202));
+        emulator.step().unwrap();
+        assert_eq!((emulator.V[0xa], emulator.pc), (0x08, 0x204));
+    }
+
+    #[test]
+    fn step_calls_and_returns() {
+        // CALL 0x206, JP 0x202, ..., RET
+        let mut emulator = boot(700, &[0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x00, 0xee]);
+        emulator.step().unwrap();
+        assert_eq!((emulator.pc, emulator.stack.as_slice()), (0x206, [0x202].as_slice()));
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc, 0x202);
+        assert!(emulator.stack.is_empty());
+    }
+
+    #[test]
+    fn errors_point_at_the_faulting_instruction() {
+        let mut emulator = boot(700, &[0x60, 0x01, 0xff, 0xff]);
+        emulator.step().unwrap();
+        let error = emulator.step().unwrap_err();
+        assert!(matches!(error, Chip8Error::UnknownOpcode { pc: 0x202, opcode: 0xffff }));
+        assert_eq!((error.pc(), error.opcode()), (Some(0x202), Some(0xffff)));
+        assert_eq!(error.to_string(), "Unknown instruction 0xFFFF at 0x202");
+        // left pointing at it, so it can be inspected or retried
+        assert_eq!(emulator.pc, 0x202);
+
+        let error = boot(700, &[0x00, 0xee]).step().unwrap_err();
+        assert!(matches!(error, Chip8Error::StackUnderflow { pc: 0x200, opcode: 0x00ee }));
+
+        // CALL 0x200, forever
+        let mut emulator = boot(700, &[0x22, 0x00]);
+        let error = emulator.run_cycles(100).unwrap_err();
+        assert!(matches!(error, Chip8Error::StackOverflow { pc: 0x200, opcode: 0x2200 }));
+        assert_eq!(emulator.stack.len(), STACK_SIZE);
+
+        // LD I, 0xFFFF, LD [I], V1
+        let error = boot(700, &[0xf0, 0x00, 0xff, 0xff, 0xf1, 0x55]).run_cycles(2).unwrap_err();
+        assert!(matches!(error, Chip8Error::MemoryOutOfBounds { pc: 0x204, opcode: 0xf155, addr: 0x10000 }));
+    }
+
+    #[test]
+    fn load_errors_have_no_context() {
+        let mut emulator = Chip8::new(700);
+        let error = emulator.load_rom_bytes(&vec![0; MEM_SIZE]).unwrap_err();
+        assert!(matches!(error, Chip8Error::RomTooLarge { size: MEM_SIZE, max } if max == MEM_SIZE - 0x200));
+        assert_eq!((error.pc(), error.opcode()), (None, None));
+
+        let error = emulator.load_rom("/nonexistent/rom.ch8").unwrap_err();
+        assert!(matches!(error, Chip8Error::Io(_)));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[test]
+    fn run_cycles_runs_n_instructions() {
+        let mut emulator = boot(700, &COUNTER);
+        emulator.run_cycles(10).unwrap();
+        assert_eq!((emulator.V[0], emulator.pc), (5, 0x200));
+    }
+
+    #[test]
+    fn run_cycles_stops_waiting_for_a_key() {
+        // LD V0, K waits in place until a key is pressed and released
+        let mut emulator = boot(700, &[0xf0, 0x0a, 0x12, 0x02]);
+        let result = emulator.run_cycles(10).unwrap();
+        assert!(result.waiting_for_key);
+        let mut keypad = [false; 16];
+        keypad[7] = true;
+        emulator.set_keypad(keypad);
+        emulator.set_keypad([false; 16]);
+        let result = emulator.run_cycles(1).unwrap();
+        assert!(!result.waiting_for_key);
+        assert_eq!((emulator.V[0], emulator.pc), (7, 0x202));
+    }
+
+    #[test]
+    fn run_frame_spreads_cycles_and_ticks_timers() {
+        // 90 instructions per second is 1.5 per frame, carried over
+        let mut emulator = boot(90, &COUNTER);
+        emulator.run_frame().unwrap();
+        assert_eq!((emulator.V[0], emulator.pc), (1, 0x202));
+        emulator.run_frame().unwrap();
+        assert_eq!((emulator.V[0], emulator.pc), (2, 0x202));
+
+        // LD V0, 3, LD DT, V0, LD ST, V0, JP 0x206
+        let mut emulator = boot(240, &[0x60, 0x03, 0xf0, 0x15, 0xf0, 0x18, 0x12, 0x06]);
+        assert!(emulator.run_frame().unwrap().beeping);
+        assert_eq!((emulator.delay_t, emulator.sound_t), (2, 2));
+        emulator.run_frame().unwrap();
+        emulator.run_frame().unwrap();
+        assert_eq!((emulator.delay_t, emulator.sound_t), (0, 0));
+        assert!(!emulator.run_frame().unwrap().beeping);
+    }
+
+    #[test]
+    fn add_sets_vf_to_the_carry() {
+        // LD V0, 0xF0, LD V1, 0x20, LD VF, 5, ADD V0, V1
+        let mut emulator = boot(700, &[0x60, 0xf0, 0x61, 0x20, 0x6f, 0x05, 0x80, 0x14]);
+        emulator.run_cycles(4).unwrap();
+        assert_eq!((emulator.V[0], emulator.V[0xf]), (0x10, 1));
+
+        // LD V0, 0x10, LD V1, 0x20, LD VF, 5, ADD V0, V1
+        let mut emulator = boot(700, &[0x60, 0x10, 0x61, 0x20, 0x6f, 0x05, 0x80, 0x14]);
+        emulator.run_cycles(4).unwrap();
+        assert_eq!((emulator.V[0], emulator.V[0xf]), (0x30, 0));
+    }
+
+    /// Coordinates of the pixels that are on
+    fn lit(emulator: &Chip8) -> Vec<(usize, usize)> {
+        let mut pixels = vec![];
+        for (y, row) in emulator.pixel_buf.iter().enumerate() {
+            for (x, &on) in row.iter().enumerate() {
+                if on != 0 {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn hires_mode_draws_16x16_sprites() {
+        // HIGH, LD I, 0x208, DRW V0, V0, 0, LOW, then 16 rows of 0x8001
+        let mut rom = vec![0x00, 0xff, 0xa2, 0x08, 0xd0, 0x00, 0x00, 0xfe];
+        rom.extend([0x80, 0x01].repeat(16));
+        let mut emulator = boot(700, &rom);
+        emulator.run_cycles(3).unwrap();
+        assert!(emulator.hires());
+        assert_eq!(emulator.resolution(), (HIRES_SCREEN_W, HIRES_SCREEN_H));
+        let expected: Vec<_> = (0..16).flat_map(|y| [(0, y), (15, y)]).collect();
+        assert_eq!(lit(&emulator), expected);
+        assert_eq!(emulator.V[0xf], 0);
+
+        emulator.step().unwrap();
+        assert!(!emulator.hires());
+        assert_eq!(emulator.resolution(), (SCREEN_W, SCREEN_H));
+        assert_eq!(lit(&emulator), []);
+    }
+
+    #[test]
+    fn scrolling_moves_the_screen() {
+        // LD I, 0x210, LD V0, 10, LD V1, 5, DRW V0, V1, 1, SCD 3, SCR, SCL, SCL, a pixel
+        let rom = [
+            0xa2, 0x10, 0x60, 0x0a, 0x61, 0x05, 0xd0, 0x11, 0x00, 0xc3, 0x00, 0xfb, 0x00, 0xfc,
+            0x00, 0xfc, 0x80,
+        ];
+        let mut emulator = boot(700, &rom);
+        emulator.run_cycles(4).unwrap();
+        assert_eq!(lit(&emulator), [(10, 5)]);
+        for expected in [(10, 8), (14, 8), (10, 8), (6, 8)] {
+            emulator.step().unwrap();
+            assert_eq!(lit(&emulator), [expected]);
+        }
+
+        // pixels scrolled past the low resolution edge are gone
+        // LD I, 0x208, LD V0, 62, DRW V0, V0, 1, SCR, a pixel
+        let rom = [0xa2, 0x08, 0x60, 0x3e, 0xd0, 0x01, 0x00, 0xfb, 0x80];
+        let mut emulator = boot(700, &rom);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(lit(&emulator), [(62, 30)]);
+        emulator.step().unwrap();
+        assert_eq!(lit(&emulator), []);
+    }
+
+    #[test]
+    fn rpl_flags_save_and_restore_registers() {
+        // LD V0-V3, 1-4, LD R, V3, LD V0-V2, 0, LD V1, R
+        let rom = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04, 0xf3, 0x75, 0x60, 0x00, 0x61, 0x00,
+            0x62, 0x00, 0xf1, 0x85,
+        ];
+        let mut emulator = boot(700, &rom);
+        emulator.run_cycles(9).unwrap();
+        assert_eq!(emulator.rpl_flags()[..5], [1, 2, 3, 4, 0]);
+        assert_eq!(emulator.V[..4], [1, 2, 0, 4]);
+    }
+
+    #[test]
+    fn exit_and_big_font() {
+        // LD V0, 3, LD HF, V0, EXIT
+        let mut emulator = boot(700, &[0x60, 0x03, 0xf0, 0x30, 0x00, 0xfd]);
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.I, BIG_FONT_ADDR + 30);
+        assert!(emulator.step().unwrap().exited);
+        assert!(emulator.step().unwrap().exited);
+        assert_eq!(emulator.pc, 0x206);
+    }
+
+    #[test]
+    fn long_load_is_skipped_whole() {
+        let mut emulator = boot(700, &[0xf0, 0x00, 0x12, 0x34]);
+        emulator.step().unwrap();
+        assert_eq!((emulator.I, emulator.pc), (0x1234, 0x204));
+
+        // SE V0, 0, LD I, LONG 0x1234
+        let mut emulator = boot(700, &[0x30, 0x00, 0xf0, 0x00, 0x12, 0x34]);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc, 0x206);
+    }
+
+    #[test]
+    fn save_and_load_register_ranges() {
+        // LD V1-V3, 1-3, LD I, 0x300, SAVE V1 - V3, LOAD V6 - V4
+        let rom = [0x61, 0x01, 0x62, 0x02, 0x63, 0x03, 0xa3, 0x00, 0x51, 0x32, 0x56, 0x43];
+        let mut emulator = boot(700, &rom);
+        emulator.run_cycles(5).unwrap();
+        assert_eq!(emulator.memory[0x300..0x304], [1, 2, 3, 0]);
+        emulator.step().unwrap();
+        assert_eq!(emulator.V[4..7], [3, 2, 1]);
+        assert_eq!(emulator.I, 0x300);
+    }
+
+    #[test]
+    fn planes_are_drawn_and_cleared_separately() {
+        // PLANE 2, LD I, 0x210, DRW V0, V0, 1, PLANE 3, DRW V0, V0, 1, PLANE 1, CLS,
+        // JP 0x20E, the sprites
+        let rom = [
+            0xf2, 0x01, 0xa2, 0x10, 0xd0, 0x01, 0xf3, 0x01, 0xd0, 0x01, 0xf1, 0x01, 0x00, 0xe0,
+            0x12, 0x0e, 0x80, 0xc0,
+        ];
+        let mut emulator = boot(700, &rom);
+        emulator.run_cycles(3).unwrap();
+        assert_eq!(emulator.pixel_buf[0][..2], [0x2, 0]);
+        assert_eq!(emulator.V[0xf], 0);
+
+        // one sprite per plane, one after the other
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.pixel_buf[0][..2], [0x1, 0x2]);
+        assert_eq!(emulator.V[0xf], 1);
+
+        emulator.run_cycles(2).unwrap();
+        assert_eq!(emulator.pixel_buf[0][..2], [0, 0x2]);
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        // LD I, 0x208, AUDIO, LD V0, 112, PITCH V0, the pattern
+        let mut rom = vec![0xa2, 0x08, 0xf0, 0x02, 0x60, 0x70, 0xf0, 0x3a];
+        rom.extend(0..16);
+        let mut emulator = boot(700, &rom);
+        assert_eq!(emulator.audio_pattern(), None);
+        assert_eq!(emulator.audio_sample_rate(), 4000.0);
+
+        emulator.run_cycles(4).unwrap();
+        assert_eq!(emulator.audio_pattern(), Some(&std::array::from_fn(|i| i as u8)));
+        assert_eq!(emulator.audio_sample_rate(), 8000.0);
+    }
+
+    #[test]
+    fn rom_hash_is_fnv1a() {
+        assert_eq!(rom_hash(&[]), 0xcbf29ce484222325);
+        assert_eq!(rom_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(boot(700, &[]).rom_hash(), rom_hash(&[]));
+
+        let mut emulator = Chip8::new(700);
+        emulator.load_rom_bytes(&COUNTER).unwrap();
+        assert_eq!(emulator.rom_hash(), rom_hash(&COUNTER));
+    }
+
+    #[test]
+    fn seeded_random_numbers_repeat() {
+        // RND V0, 0xFF, RND V1, 0xFF, RND V2, 0x0F
+        let rom = [0xc0, 0xff, 0xc1, 0xff, 0xc2, 0x0f];
+        let run = |seed| {
+            let mut emulator = boot(700, &rom);
+            emulator.set_seed(seed);
+            emulator.run_cycles(3).unwrap();
+            assert_eq!(emulator.seed(), seed);
+            emulator.V
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+        assert_eq!(run(1)[2] & 0xf0, 0);
+    }
+}
@@ -1,9 +1,41 @@
+use std::fs::{ self, File };
+use std::io::{ self, BufWriter, Write };
+use std::ops::RangeInclusive;
+use std::process;
+#[cfg(feature = "gui")]
+use std::path::{ Path, PathBuf };
+#[cfg(feature = "gui")]
 use std::thread;
-use std::sync::{ Arc, Mutex };
-use clap::Parser;
+#[cfg(feature = "gui")]
+use std::sync::{ Arc, Mutex, mpsc::{ self, Receiver, TryRecvError } };
+#[cfg(feature = "gui")]
+use std::time::{ Duration, Instant };
+use clap::{ Args, CommandFactory, Parser, Subcommand, ValueEnum, error::ErrorKind };
 
+// everything but headless runs needs the window, keyboard and audio crates
+#[cfg(feature = "gui")]
+pub mod audio;
+#[cfg(feature = "gui")]
+pub mod debugger;
+#[cfg(feature = "gui")]
 pub mod display;
-use display::Display;
+#[cfg(feature = "gui")]
+pub mod gdb;
+#[cfg(feature = "gui")]
+pub mod keymap;
+pub mod palette;
+pub mod saves;
+#[cfg(feature = "gui")]
+use audio::Buzzer;
+#[cfg(feature = "gui")]
+use debugger::{ Debugger, Poll };
+#[cfg(feature = "gui")]
+use display::{ Display, Hotkey, Screen };
+#[cfg(feature = "gui")]
+use gdb::GdbStub;
+#[cfg(feature = "gui")]
+use keymap::Keymap;
+use palette::Palette;
 use chip8::*;
 
 fn main() {
@@ -12,50 +44,657 @@ fn main() {
     unsafe {
         DEBUG_ENABLED = cli.debug;
     }
-    let rom_file = cli.rom;
-    let ips = cli.ips;
-    println_debug!("IPS:\t{}", ips);
-    println_debug!("ROM:\t{}", rom_file);
+    match cli.command.or(cli.run.map(Command::Run)) {
+        Some(Command::Run(args)) => run(args),
+        Some(Command::Disasm(args)) => disasm(args),
+        Some(Command::Asm(args)) => asm(args),
+        None => {
+            Cli::command().error(ErrorKind::MissingRequiredArgument, "no ROM given").exit();
+        }
+    }
+}
+
+/// Executes the ROM, in real time in a window or headless
+fn run(args: RunArgs) {
+    println_debug!("IPS:\t{}", args.ips);
+    println_debug!("Platform:\t{}", args.platform);
+    println_debug!("ROM:\t{}", args.rom);
     println_debug!("Debug:\tyes");
 
-    let mut display = Display::create_window();
-    display.set_window_title(format!("CHIP-8  -  {}", rom_file));
+    if args.headless {
+        run_headless(args);
+        return;
+    }
+    run_windowed(args);
+}
 
-    let mut emulator = Chip8::new(ips, display.create_pixel_buf());
-    match emulator.load_rom(&rom_file) {
-        Ok(_) => {}
-        Err(_) => {
-            println!("Failed to load ROM");
+/// Opens a window and executes the ROM in real time
+#[cfg(not(feature = "gui"))]
+fn run_windowed(_args: RunArgs) {
+    println!("Failed to open a window: built without the gui feature, only --headless is available");
+    process::exit(1);
+}
+
+/// Opens a window and executes the ROM in real time
+#[cfg(feature = "gui")]
+fn run_windowed(args: RunArgs) {
+    let keymap = match Keymap::load(args.keymap.as_deref()) {
+        Ok(keymap) => keymap,
+        Err(why) => {
+            println!("Failed to load keymap: {why}");
             return;
         }
+    };
+    let Some((mut emulator, movie)) = setup_emulator(&args) else {
+        return;
+    };
+    // saved flags would make the run play out differently than the recording
+    if let MovieMode::Off = movie {
+        if let Some(flags) = saves::load_rpl_flags(emulator.rom_hash()) {
+            emulator.set_rpl_flags(flags);
+        }
     }
 
+    let mut display = Display::create_window();
+    display.set_window_title(format!("CHIP-8  -  {}", args.rom));
+    let mut screen = display.create_screen(args.palette);
+
     let keypad_state: Arc<Mutex<[bool; 16]>> = Arc::new(Mutex::new([false; 16]));
     let keypad_state2 = keypad_state.clone();
-    //let (key_tx, key_rx) = mpsc::channel();
+    let (hotkey_tx, hotkey_rx) = mpsc::channel();
+    let closer = display.create_closer();
 
     // starting emulator thread
-    match
+    let emulator_thread = match
         thread::Builder
             ::new()
             .name("emulator_thread".to_string())
             .spawn(move || {
-                emulator.run(keypad_state2);
+                // the window stays open until it's closed, so quitting from
+                // a debugger closes it once everything's written out
+                if run_emulator(&mut emulator, &args, keypad_state2, hotkey_rx, movie, &mut screen) {
+                    let _ = closer.send_event(());
+                }
             })
     {
+        Ok(handle) => handle,
         Err(e) => {
             println!("Failed to spawn emulator thread: {e}");
             return;
         }
-        Ok(_) => {}
+    };
+    display.run_event_loop(keymap, keypad_state, hotkey_tx);
+    // closing the window hangs up the hotkey channel, which stops the
+    // emulator thread (if it hasn't stopped already), let it finish writing
+    // any files
+    let _ = emulator_thread.join();
+}
+
+/// Creates the emulator and loads the ROM, set up from the command line or
+/// the movie being played
+fn setup_emulator(args: &RunArgs) -> Option<(Chip8, MovieMode)> {
+    // a movie brings its own settings
+    let playing = match &args.play {
+        Some(path) => {
+            let movie = fs::read_to_string(path)
+                .map_err(|why| why.to_string())
+                .and_then(|text| Movie::parse(&text));
+            match movie {
+                Ok(movie) => Some(movie),
+                Err(why) => {
+                    println!("Failed to read movie {path}: {why}");
+                    return None;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let mut emulator = Chip8::new(playing.as_ref().map_or(args.ips, |movie| movie.ips));
+    emulator.set_quirks(playing.as_ref().map_or(args.platform.quirks(), |movie| movie.quirks));
+    if let Some(seed) = playing.as_ref().map(|movie| movie.seed).or(args.seed) {
+        emulator.set_seed(seed);
+    }
+    println_debug!("Seed:\t{}", emulator.seed());
+    if let Err(why) = emulator.load_rom(&args.rom) {
+        println!("Failed to load ROM: {why}");
+        return None;
+    }
+    if playing.as_ref().is_some_and(|movie| movie.rom_hash != emulator.rom_hash()) {
+        println!("Warning: the movie was recorded with a different ROM");
+    }
+
+    let movie = match (playing, &args.record) {
+        (Some(movie), _) => MovieMode::Play(movie),
+        (None, Some(path)) => MovieMode::Record { path: path.clone(), movie: Movie::new(&emulator) },
+        (None, None) => MovieMode::Off,
+    };
+    Some((emulator, movie))
+}
+
+/// Runs the ROM for a fixed number of frames as fast as possible, without a
+/// window or audio, then dumps the framebuffer
+///
+/// Exits with status 1 if anything fails, so it can be used in scripts.
+fn run_headless(args: RunArgs) {
+    let Some((mut emulator, movie)) = setup_emulator(&args) else {
+        process::exit(1);
+    };
+    let trace = match start_trace(&args) {
+        Ok(trace) => trace,
+        Err(()) => process::exit(1),
+    };
+    let mut observers = (trace, args.profile.is_some().then(Profile::new), args.coverage.is_some().then(Coverage::new));
+    let frames = args.frames.unwrap_or_default();
+    for frame in 0..frames {
+        if let MovieMode::Play(movie) = &movie {
+            emulator.set_keypad(movie.keypad(frame));
+        }
+        match emulator.run_frame_observed(&mut observers) {
+            Ok(result) if result.exited => {
+                println_debug!("Exited after {} frames", frame + 1);
+                break;
+            }
+            Ok(_) => {}
+            Err(why) => {
+                println!("Failed after {frame} frames: {why}");
+                finish_trace(&args, observers.0);
+                finish_profile(&args, &emulator, observers.1);
+                finish_coverage(&args, observers.2);
+                process::exit(1);
+            }
+        }
+    }
+    let traced = finish_trace(&args, observers.0);
+    let profiled = finish_profile(&args, &emulator, observers.1);
+    if !finish_coverage(&args, observers.2) || !profiled || !traced {
+        process::exit(1);
+    }
+
+    let (pixel_buf, hires) = (emulator.pixel_buf(), emulator.hires());
+    let dump = match args.dump {
+        DumpFormat::Text => framebuffer_to_text(pixel_buf, hires).into_bytes(),
+        DumpFormat::Pbm => framebuffer_to_pbm(pixel_buf, hires),
+        DumpFormat::Png => framebuffer_to_png(pixel_buf, hires, 1, &args.palette),
+        DumpFormat::Hash => format!("{:016x}\n", framebuffer_hash(pixel_buf, hires)).into_bytes(),
+    };
+    let written = match &args.output {
+        Some(path) => fs::write(path, &dump),
+        None => io::stdout().write_all(&dump),
+    };
+    if let Err(why) = written {
+        println!("Failed to write framebuffer: {why}");
+        process::exit(1);
+    }
+}
+
+/// Input movie being recorded or played back by the frame loop
+// headless runs can't record, so without the window nothing reads these
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+enum MovieMode {
+    Off,
+    Record {
+        path: String,
+        movie: Movie,
+    },
+    Play(Movie),
+}
+
+/// Prints a disassembly listing of the ROM
+fn disasm(args: DisasmArgs) {
+    let rom = match fs::read(&args.rom) {
+        Ok(rom) => rom,
+        Err(why) => {
+            println!("Failed to read ROM: {why}");
+            process::exit(1);
+        }
+    };
+
+    let lines = if args.linear { disassemble_linear(&rom) } else { disassemble(&rom) };
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+/// Assembles a source file into a ROM
+fn asm(args: AsmArgs) {
+    let source = match fs::read_to_string(&args.input) {
+        Ok(source) => source,
+        Err(why) => {
+            println!("Failed to read {}: {why}", args.input);
+            process::exit(1);
+        }
+    };
+
+    let assembly = match assemble(&source) {
+        Ok(assembly) => assembly,
+        Err(errors) => {
+            for error in errors {
+                println!("{}: {error}", args.input);
+            }
+            process::exit(1);
+        }
+    };
+    println_debug!("Assembled {} bytes", assembly.rom.len());
+
+    if let Err(why) = fs::write(&args.output, &assembly.rom) {
+        println!("Failed to write {}: {why}", args.output);
+        process::exit(1);
+    }
+}
+
+/// Real-time execution loop, runs one frame of instructions every 60hz tick
+///
+/// Returns whether the debugger or a GDB client asked to quit.
+#[cfg(feature = "gui")]
+fn run_emulator(
+    emulator: &mut Chip8,
+    args: &RunArgs,
+    keypad_state: Arc<Mutex<[bool; 16]>>,
+    hotkeys: Receiver<Hotkey>,
+    mut movie: MovieMode,
+    screen: &mut Screen
+) -> bool {
+    screen.render(emulator.pixel_buf(), emulator.hires());
+    let mut buzzer = Buzzer::create();
+    let mut saved_rpl_flags = *emulator.rpl_flags();
+    let rom_hash = emulator.rom_hash();
+    let mut rewind = Rewind::new(args.rewind_frames, args.rewind_memory.saturating_mul(1024 * 1024));
+    let mut rewinding = false;
+    let mut frame: u64 = 0;
+    let mut quit = false;
+    let mut capture: Option<(PathBuf, Capture<BufWriter<File>>)> = None;
+    let mut debugger = args.debugger.then(Debugger::start);
+    let mut observers = (
+        start_trace(args).unwrap_or_default(),
+        args.profile.is_some().then(Profile::new),
+        args.coverage.is_some().then(Coverage::new),
+    );
+    let mut gdb = args.gdb.and_then(|port| {
+        GdbStub::listen(port)
+            .map_err(|why| println!("Failed to listen for GDB on port {port}: {why}"))
+            .ok()
+    });
+
+    println_debug!("Starting execution\n");
+    let time_per_tick = Duration::from_secs_f64(1.0 / (REFRESH_RATE as f64));
+    let mut next_tick_time = Instant::now();
+    'frames: loop {
+        loop {
+            let hotkey = match hotkeys.try_recv() {
+                Ok(hotkey) => hotkey,
+                Err(TryRecvError::Empty) => {
+                    break;
+                }
+                Err(TryRecvError::Disconnected) => {
+                    break 'frames;
+                }
+            };
+            match hotkey {
+                Hotkey::Rewind(held) => rewinding = held,
+                Hotkey::ToggleCapture => {
+                    capture = match capture.take() {
+                        Some((path, capture)) => {
+                            finish_capture(&path, capture);
+                            None
+                        }
+                        None => start_capture(args),
+                    };
+                }
+                // jumping somewhere else entirely would desync the movie
+                Hotkey::LoadState(_) if !matches!(movie, MovieMode::Off) => {
+                    println!("Can't load states while recording or playing a movie");
+                }
+                hotkey => {
+                    handle_hotkey(emulator, args, rom_hash, hotkey);
+                    screen.render(emulator.pixel_buf(), emulator.hires());
+                }
+            }
+        }
+
+        if let Some((path, recording)) = &mut capture {
+            // whatever's on screen this tick, also while rewinding
+            if let Err(why) = recording.add_frame(emulator.pixel_buf(), emulator.hires()) {
+                println!("Failed to write {}: {why}", path.display());
+                capture = None;
+            }
+        }
+
+        if let Some(debugger) = &mut debugger {
+            let poll = debugger.poll(emulator, &mut observers);
+            if poll.drew {
+                screen.render(emulator.pixel_buf(), emulator.hires());
+            }
+            if poll.quit {
+                quit = true;
+                break;
+            }
+        }
+        if let Some(gdb) = &mut gdb {
+            let poll = gdb.poll(emulator, &mut observers);
+            if poll.drew {
+                screen.render(emulator.pixel_buf(), emulator.hires());
+            }
+            if poll.quit {
+                quit = true;
+                break;
+            }
+        }
+
+        if rewinding {
+            // one frame back per tick, frozen once the buffer runs out
+            if rewind.step_back(emulator) {
+                frame = frame.saturating_sub(1);
+                if let MovieMode::Record { movie, .. } = &mut movie {
+                    movie.truncate(frame);
+                }
+                screen.render(emulator.pixel_buf(), emulator.hires());
+            }
+            buzzer.set_active(false);
+            wait_for_tick(&mut next_tick_time, time_per_tick);
+            continue;
+        }
+
+        if debugger.as_ref().is_some_and(Debugger::paused) || gdb.as_ref().is_some_and(GdbStub::paused) {
+            buzzer.set_active(false);
+            wait_for_tick(&mut next_tick_time, time_per_tick);
+            continue;
+        }
+
+        let keypad = match &mut movie {
+            MovieMode::Play(movie) if frame < movie.frames => movie.keypad(frame),
+            MovieMode::Record { movie, .. } => {
+                let keypad = *keypad_state.lock().unwrap();
+                movie.record(frame, keypad);
+                keypad
+            }
+            _ => *keypad_state.lock().unwrap(),
+        };
+        if let MovieMode::Play(playing) = &movie {
+            if frame == playing.frames {
+                println!("Movie finished at frame {frame}, switching to live input");
+            }
+        }
+        emulator.set_keypad(keypad);
+        frame += 1;
+        let result = emulator.run_frame_observed(&mut observers);
+        if let Some(debugger) = &mut debugger {
+            debugger.frame_done(emulator, &result);
+        }
+        if let Some(gdb) = &mut gdb {
+            gdb.frame_done(emulator, &result);
+        }
+        // debuggers keep the machine around for inspection
+        let debugging = debugger.is_some() || gdb.is_some();
+        match result {
+            Ok(result) => {
+                rewind.record(emulator);
+                if result.drew {
+                    screen.render(emulator.pixel_buf(), emulator.hires());
+                }
+                buzzer.set_pattern(emulator.audio_pattern(), emulator.audio_sample_rate());
+                buzzer.set_active(result.beeping);
+                // a movie's run starts from cleared flags, its high scores
+                // mustn't replace the player's own
+                if *emulator.rpl_flags() != saved_rpl_flags && matches!(movie, MovieMode::Off) {
+                    saved_rpl_flags = *emulator.rpl_flags();
+                    if let Err(why) = saves::save_rpl_flags(emulator.rom_hash(), &saved_rpl_flags) {
+                        println!("Failed to save RPL flags: {why}");
+                    }
+                }
+                if result.exited && !debugging {
+                    break;
+                }
+            }
+            Err(why) => {
+                println!("Failed: {why}");
+                if !debugging {
+                    break;
+                }
+            }
+        }
+
+        wait_for_tick(&mut next_tick_time, time_per_tick);
+    }
+    buzzer.set_active(false);
+    if let Some((path, capture)) = capture {
+        finish_capture(&path, capture);
+    }
+    finish_trace(args, observers.0);
+    finish_profile(args, emulator, observers.1);
+    finish_coverage(args, observers.2);
+    if let MovieMode::Record { path, movie } = movie {
+        match fs::write(&path, movie.to_string()) {
+            Ok(()) => println!("Saved movie of {} frames to {path}", movie.frames),
+            Err(why) => println!("Failed to write movie {path}: {why}"),
+        }
+    }
+    println_debug!("Completed execution");
+    quit
+}
+
+/// Opens a timestamped file and starts recording the screen into it
+#[cfg(feature = "gui")]
+fn start_capture(args: &RunArgs) -> Option<(PathBuf, Capture<BufWriter<File>>)> {
+    let path = saves::timestamped_path("chip8", args.capture_format.extension());
+    let capture = File::create(&path).and_then(|file| {
+        Capture::new(BufWriter::new(file), args.capture_format, args.capture_scale as usize, &args.palette)
+    });
+    match capture {
+        Ok(capture) => {
+            println!("Recording to {}", path.display());
+            Some((path, capture))
+        }
+        Err(why) => {
+            println!("Failed to start recording: {why}");
+            None
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
+fn finish_capture(path: &Path, capture: Capture<BufWriter<File>>) {
+    let ticks = capture.ticks();
+    match capture.finish() {
+        Ok(_) => println!("Saved {} seconds of recording to {}", ticks / (REFRESH_RATE as u64), path.display()),
+        Err(why) => println!("Failed to write {}: {why}", path.display()),
+    }
+}
+
+/// Opens the `--trace` file, if any
+fn start_trace(args: &RunArgs) -> Result<Option<Trace<BufWriter<File>>>, ()> {
+    let Some(path) = &args.trace else {
+        return Ok(None);
+    };
+    match File::create(path) {
+        Ok(file) => {
+            let range = args.trace_range.clone().unwrap_or(0..=u16::MAX);
+            Ok(Some(Trace::new(BufWriter::new(file), range, args.trace_limit.saturating_mul(1024 * 1024))))
+        }
+        Err(why) => {
+            println!("Failed to create trace {path}: {why}");
+            Err(())
+        }
+    }
+}
+
+/// Returns whether the trace was written out fine
+fn finish_trace(args: &RunArgs, trace: Option<Trace<BufWriter<File>>>) -> bool {
+    let (Some(path), Some(trace)) = (&args.trace, trace) else {
+        return true;
+    };
+    if trace.truncated() {
+        println!("Trace {path} reached the size limit of {} MB", args.trace_limit);
+    }
+    let cycles = trace.cycles();
+    match trace.finish() {
+        Ok(_) => {
+            println_debug!("Traced {cycles} instructions to {path}");
+            true
+        }
+        Err(why) => {
+            println!("Failed to write trace {path}: {why}");
+            false
+        }
+    }
+}
+
+/// Writes the `--profile` report, returns whether that went fine
+fn finish_profile(args: &RunArgs, emulator: &Chip8, profile: Option<Profile>) -> bool {
+    let (Some(path), Some(profile)) = (&args.profile, profile) else {
+        return true;
+    };
+    let report = profile.report(emulator);
+    let written = match path.as_str() {
+        "-" => io::stdout().write_all(report.as_bytes()),
+        _ => fs::write(path, &report),
+    };
+    match written {
+        Ok(()) => true,
+        Err(why) => {
+            println!("Failed to write profile {path}: {why}");
+            false
+        }
+    }
+}
+
+/// Writes the `--coverage` report, returns whether that went fine
+fn finish_coverage(args: &RunArgs, coverage: Option<Coverage>) -> bool {
+    let (Some(path), Some(coverage)) = (&args.coverage, coverage) else {
+        return true;
+    };
+    let rom = match fs::read(&args.rom) {
+        Ok(rom) => rom,
+        Err(why) => {
+            println!("Failed to read ROM {}: {why}", args.rom);
+            return false;
+        }
+    };
+    // the symbols only mean something if the source is what the ROM was built from
+    let assembly = args.coverage_source.as_ref().and_then(|source_path| {
+        let assembly = fs::read_to_string(source_path)
+            .map_err(|why| why.to_string())
+            .and_then(|source| {
+                assemble(&source).map_err(|errors| {
+                    errors
+                        .iter()
+                        .map(|error| error.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                })
+            });
+        match assembly {
+            Ok(assembly) if assembly.rom == rom => Some((source_path, assembly)),
+            Ok(_) => {
+                println!("Warning: {source_path} doesn't assemble to {}, ignoring it", args.rom);
+                None
+            }
+            Err(why) => {
+                println!("Failed to assemble {source_path}: {why}");
+                None
+            }
+        }
+    });
+
+    let report = match (args.coverage_format, assembly) {
+        (CoverageFormat::Listing, assembly) => {
+            let labels = assembly.map(|(_, assembly)| assembly.labels).unwrap_or_default();
+            coverage.listing(&rom, &labels)
+        }
+        (CoverageFormat::Lcov, Some((source_path, assembly))) => coverage.lcov(source_path, &assembly),
+        (CoverageFormat::Lcov, None) => {
+            println!("Failed to write coverage: lcov output needs the source the ROM was assembled from");
+            return false;
+        }
+    };
+    match fs::write(path, report) {
+        Ok(()) => true,
+        Err(why) => {
+            println!("Failed to write coverage {path}: {why}");
+            false
+        }
+    }
+}
+
+/// Sleeps until the next 60hz tick
+#[cfg(feature = "gui")]
+fn wait_for_tick(next_tick_time: &mut Instant, time_per_tick: Duration) {
+    *next_tick_time += time_per_tick;
+    let now = Instant::now();
+    if *next_tick_time > now {
+        thread::sleep(*next_tick_time - now);
+    } else {
+        // running behind, don't try to catch up
+        *next_tick_time = now;
+    }
+}
+
+#[cfg(feature = "gui")]
+fn handle_hotkey(emulator: &mut Chip8, args: &RunArgs, rom_hash: u64, hotkey: Hotkey) {
+    match hotkey {
+        Hotkey::Screenshot => {
+            let scale = args.screenshot_scale as usize;
+            let png = framebuffer_to_png(emulator.pixel_buf(), emulator.hires(), scale, &args.palette);
+            let path = saves::timestamped_path("chip8", "png");
+            match fs::write(&path, png) {
+                Ok(()) => println!("Saved screenshot to {}", path.display()),
+                Err(why) => println!("Failed to save screenshot: {why}"),
+            }
+        }
+        Hotkey::SaveState(slot) => {
+            match saves::save_state(rom_hash, slot, &emulator.save_state()) {
+                Ok(path) => println!("Saved state {slot} to {}", path.display()),
+                Err(why) => println!("Failed to save state {slot}: {why}"),
+            }
+        }
+        Hotkey::LoadState(slot) => {
+            let state = match saves::load_state(rom_hash, slot) {
+                Ok(state) => state,
+                Err(why) => {
+                    println!("Failed to load state {slot}: {why}");
+                    return;
+                }
+            };
+            match emulator.load_state(&state) {
+                Ok(()) => println!("Loaded state {slot}"),
+                Err(why) => println!("Failed to load state {slot}: {why}"),
+            }
+        }
+        // handled by the frame loop
+        Hotkey::Rewind(_) | Hotkey::ToggleCapture => {}
     }
-    display.run_event_loop(keypad_state);
 }
 
 // Argument parsing stuff
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: Option<RunArgs>,
+
+    /// Print debug information
+    #[arg(short, long, global = true)]
+    debug: bool,
+}
+
+// parsed once at startup, the size doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Execute a ROM (default when no command is given)
+    Run(RunArgs),
+    /// Print a disassembly listing of a ROM
+    Disasm(DisasmArgs),
+    /// Assemble a source file into a ROM
+    Asm(AsmArgs),
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
     /// ROM file to execute
     rom: String,
 
@@ -63,7 +702,163 @@ struct Cli {
     #[arg(long, default_value_t = 700)]
     ips: usize,
 
-    /// Print debug information
-    #[arg(short, long)]
-    debug: bool,
+    /// Interpreter to emulate the quirks of (vip, chip48, xochip, or schip
+    /// for the same quirks as chip48)
+    #[arg(long, default_value_t = Platform::default())]
+    platform: Platform,
+
+    /// Seed for the random number generator (CXNN), random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Frames kept for rewinding (0 disables rewinding)
+    #[arg(long, default_value_t = 600)]
+    rewind_frames: usize,
+
+    /// Memory the rewind buffer may use, in MB
+    #[arg(long, default_value_t = 32)]
+    rewind_memory: usize,
+
+    /// Colours of the screen, screenshots and recordings: default, vip,
+    /// amber, lcd, high-contrast, or hex colours OFF,ON[,PLANE2,BOTH]
+    #[arg(long, value_name = "THEME|COLORS", default_value = "default", value_parser = palette::parse_palette)]
+    palette: Palette,
+
+    /// Image pixels per CHIP-8 pixel in screenshots
+    #[arg(long, value_name = "SCALE", default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..=64))]
+    screenshot_scale: u16,
+
+    /// Format of recordings started with F10 (gif, y4m)
+    #[arg(long, value_name = "FORMAT", default_value_t = CaptureFormat::Gif)]
+    capture_format: CaptureFormat,
+
+    /// Image pixels per high resolution pixel in recordings
+    #[arg(long, value_name = "SCALE", default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..=16))]
+    capture_scale: u16,
+
+    /// Keyboard layout (physical, azerty, qwertz, numpad) or keymap file,
+    /// defaults to keymap.conf in the config directory if there is one
+    #[arg(long, value_name = "PRESET|FILE")]
+    keymap: Option<String>,
+
+    /// Record keypad input to a movie file
+    #[arg(long, value_name = "FILE", conflicts_with = "play")]
+    record: Option<String>,
+
+    /// Play back a movie file recorded with --record, instead of taking input
+    #[arg(long, value_name = "FILE")]
+    play: Option<String>,
+
+    /// Run without a window or audio, then dump the framebuffer
+    #[arg(long, requires = "frames", conflicts_with_all = ["record", "debugger", "gdb"])]
+    headless: bool,
+
+    /// Start paused, taking debugger commands on stdin
+    #[arg(long, conflicts_with = "gdb")]
+    debugger: bool,
+
+    /// Start paused, waiting for GDB to connect on a local TCP port
+    #[arg(long, value_name = "PORT")]
+    gdb: Option<u16>,
+
+    /// Log every executed instruction to a file
+    #[arg(long, value_name = "FILE")]
+    trace: Option<String>,
+
+    /// Only log instructions within an address range, like 200-2ff
+    #[arg(long, value_name = "START-END", value_parser = parse_addr_range, requires = "trace")]
+    trace_range: Option<RangeInclusive<u16>>,
+
+    /// Size the trace file may grow to, in MB
+    #[arg(long, value_name = "MB", default_value_t = 64, requires = "trace")]
+    trace_limit: u64,
+
+    /// Count where execution time goes, writing a report to a file on exit
+    /// ('-' for stdout)
+    #[arg(long, value_name = "FILE")]
+    profile: Option<String>,
+
+    /// Track which ROM bytes were executed, read and written, writing a
+    /// report to a file on exit
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<String>,
+
+    /// Format of the coverage report
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = CoverageFormat::Listing, requires = "coverage")]
+    coverage_format: CoverageFormat,
+
+    /// Assembly source of the ROM, for labels in the coverage report
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "coverage",
+        required_if_eq("coverage_format", "lcov")
+    )]
+    coverage_source: Option<String>,
+
+    /// Frames to run for in headless mode (60 per second)
+    #[arg(long, requires = "headless")]
+    frames: Option<u64>,
+
+    /// Format of the headless framebuffer dump
+    #[arg(long, value_enum, default_value_t = DumpFormat::Text, requires = "headless")]
+    dump: DumpFormat,
+
+    /// File to write the headless framebuffer dump to, instead of stdout
+    #[arg(long, short, value_name = "FILE", requires = "headless")]
+    output: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DumpFormat {
+    /// Text art, '#' for lit pixels
+    Text,
+    /// Binary PBM image
+    Pbm,
+    /// PNG image in the window's colours
+    Png,
+    /// Hash of the framebuffer
+    Hash,
+}
+
+/// Parses an inclusive range of hex addresses, like `200-2ff`
+fn parse_addr_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse_addr = |addr: &str| {
+        let digits = addr.trim_start_matches("0x").trim_start_matches("0X");
+        u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{addr}'"))
+    };
+    let (start, end) = s.split_once('-').ok_or("expected a range like 200-2ff")?;
+    let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+    if end < start {
+        return Err("the range ends before it starts".to_string());
+    }
+    Ok(start..=end)
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum CoverageFormat {
+    /// Disassembly marked with what was executed, read and written
+    Listing,
+    /// lcov tracefile of the assembly source, for genhtml and editors
+    Lcov,
+}
+
+#[derive(Args, Debug)]
+struct DisasmArgs {
+    /// ROM file to disassemble
+    rom: String,
+
+    /// Decode every word as an instruction instead of following the control flow
+    #[arg(long)]
+    linear: bool,
+}
+
+#[derive(Args, Debug)]
+struct AsmArgs {
+    /// Source file to assemble
+    input: String,
+
+    /// ROM file to write
+    #[arg(short, long, default_value = "out.ch8")]
+    output: String,
 }
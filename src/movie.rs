@@ -0,0 +1,315 @@
+use std::fmt;
+
+use crate::{ Chip8, Quirks };
+
+/// Identifies a movie file, followed by the format version
+const HEADER: &str = "chip8-movie 1";
+
+/// Keypad input recorded frame by frame, for replaying a run exactly
+///
+/// Along with the input, a movie holds everything else a run depends on: the
+/// ROM (by hash), the seed of the random number generator, the speed and the
+/// quirks. Replaying it from a fresh emulator set up with these reproduces
+/// the original run.
+///
+/// Movies are plain text, so they're easy to attach to bug reports:
+///
+/// ```text
+/// chip8-movie 1
+/// rom 8a3d0c5e1f4b7a92
+/// seed 42
+/// ips 700
+/// quirks jump_vx
+/// frames 1200
+/// 30 0020
+/// 45 0000
+/// ```
+///
+/// Each input line is a frame number and the keypad state from that frame
+/// on, as a bitmask with key 0 as the lowest bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub ips: usize,
+    pub quirks: Quirks,
+    /// Length of the run in frames
+    pub frames: u64,
+    /// Frame numbers and the keypad state from then on, in order
+    inputs: Vec<(u64, u16)>,
+}
+
+impl Movie {
+    /// Starts an empty movie of a run of the emulator as it is now
+    pub fn new(emulator: &Chip8) -> Self {
+        Self {
+            rom_hash: emulator.rom_hash(),
+            seed: emulator.seed(),
+            ips: emulator.ips(),
+            quirks: emulator.quirks(),
+            frames: 0,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Records the keypad state of a frame, frames have to come in order
+    pub fn record(&mut self, frame: u64, keypad: [bool; 16]) {
+        let keys = to_bitmask(keypad);
+        let last_keys = self.inputs.last().map_or(0, |&(_, keys)| keys);
+        if keys != last_keys {
+            self.inputs.push((frame, keys));
+        }
+        self.frames = self.frames.max(frame + 1);
+    }
+
+    /// Drops everything recorded from `frame` on, e.g. after rewinding
+    pub fn truncate(&mut self, frame: u64) {
+        self.inputs.retain(|&(input_frame, _)| input_frame < frame);
+        self.frames = self.frames.min(frame);
+    }
+
+    /// Keypad state during a frame, with every key released once the movie
+    /// is over
+    pub fn keypad(&self, frame: u64) -> [bool; 16] {
+        if frame >= self.frames {
+            return [false; 16];
+        }
+        let i = self.inputs.partition_point(|&(input_frame, _)| input_frame <= frame);
+        let keys = if i == 0 { 0 } else { self.inputs[i - 1].1 };
+        let mut keypad = [false; 16];
+        for (key, pressed) in keypad.iter_mut().enumerate() {
+            *pressed = keys & (1 << key) != 0;
+        }
+        keypad
+    }
+
+    /// Parses a movie file
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            Some((_, line)) if line.starts_with("chip8-movie") => {
+                return Err(format!("line 1: unsupported movie version, expected '{HEADER}'"));
+            }
+            _ => {
+                return Err("line 1: not a movie file".to_string());
+            }
+        }
+
+        let (mut rom_hash, mut seed, mut ips, mut quirks, mut frames) = (None, None, None, None, None);
+        let mut inputs: Vec<(u64, u16)> = Vec::new();
+        for (line_number, line) in lines {
+            let error = |message: &str| format!("line {line_number}: {message}");
+            let (key, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let value = value.trim();
+            match key {
+                "rom" => {
+                    rom_hash = Some(u64::from_str_radix(value, 16).map_err(|_| error("invalid ROM hash"))?);
+                }
+                "seed" => {
+                    seed = Some(value.parse().map_err(|_| error("invalid seed"))?);
+                }
+                "ips" => {
+                    ips = Some(value.parse().map_err(|_| error("invalid ips"))?);
+                }
+                "quirks" => {
+                    quirks = Some(parse_quirks(value).map_err(|why| error(&why))?);
+                }
+                "frames" => {
+                    frames = Some(value.parse().map_err(|_| error("invalid frame count"))?);
+                }
+                _ => {
+                    let frame: u64 = key.parse().map_err(|_| error(&format!("unknown field '{key}'")))?;
+                    let keys = u16::from_str_radix(value, 16).map_err(|_| error("invalid keypad state"))?;
+                    if inputs.last().is_some_and(|&(last_frame, _)| last_frame >= frame) {
+                        return Err(error("frames are out of order"));
+                    }
+                    inputs.push((frame, keys));
+                }
+            }
+        }
+
+        let missing = |field: &str| format!("missing '{field}'");
+        let mut movie = Movie {
+            rom_hash: rom_hash.ok_or_else(|| missing("rom"))?,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            ips: ips.ok_or_else(|| missing("ips"))?,
+            quirks: quirks.ok_or_else(|| missing("quirks"))?,
+            frames: frames.ok_or_else(|| missing("frames"))?,
+            inputs,
+        };
+        if let Some(&(last_frame, _)) = movie.inputs.last() {
+            movie.frames = movie.frames.max(last_frame + 1);
+        }
+        Ok(movie)
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{HEADER}")?;
+        writeln!(f, "rom {:016x}", self.rom_hash)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "ips {}", self.ips)?;
+        writeln!(f, "quirks {}", format_quirks(&self.quirks))?;
+        writeln!(f, "frames {}", self.frames)?;
+        for (frame, keys) in self.inputs.iter() {
+            writeln!(f, "{frame} {keys:04x}")?;
+        }
+        Ok(())
+    }
+}
+
+fn to_bitmask(keypad: [bool; 16]) -> u16 {
+    keypad
+        .iter()
+        .enumerate()
+        .filter(|(_, &pressed)| pressed)
+        .fold(0, |keys, (key, _)| keys | (1 << key))
+}
+
+/// Names of the quirks, in the order of `quirk_flags`
+const QUIRK_NAMES: [&str; 6] = ["shift_vy", "memory_increment", "jump_vx", "vf_reset", "wrap_sprites", "display_wait"];
+
+fn quirk_flags(quirks: &mut Quirks) -> [&mut bool; 6] {
+    [
+        &mut quirks.shift_vy,
+        &mut quirks.memory_increment,
+        &mut quirks.jump_vx,
+        &mut quirks.vf_reset,
+        &mut quirks.wrap_sprites,
+        &mut quirks.display_wait,
+    ]
+}
+
+/// Comma separated names of the enabled quirks, or `none`
+fn format_quirks(quirks: &Quirks) -> String {
+    let mut quirks = *quirks;
+    let enabled: Vec<&str> = QUIRK_NAMES
+        .iter()
+        .zip(quirk_flags(&mut quirks))
+        .filter(|(_, enabled)| **enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    if enabled.is_empty() { "none".to_string() } else { enabled.join(",") }
+}
+
+fn parse_quirks(s: &str) -> Result<Quirks, String> {
+    let mut quirks = Quirks {
+        shift_vy: false,
+        memory_increment: false,
+        jump_vx: false,
+        vf_reset: false,
+        wrap_sprites: false,
+        display_wait: false,
+    };
+    if s == "none" {
+        return Ok(quirks);
+    }
+    for name in s.split(',').map(str::trim) {
+        let i = QUIRK_NAMES
+            .iter()
+            .position(|quirk_name| *quirk_name == name)
+            .ok_or_else(|| format!("unknown quirk '{name}'"))?;
+        *quirk_flags(&mut quirks)[i] = true;
+    }
+    Ok(quirks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Platform;
+
+    fn keypad(keys: &[usize]) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for &key in keys {
+            keypad[key] = true;
+        }
+        keypad
+    }
+
+    fn recorded_movie() -> Movie {
+        let mut emulator = Chip8::new(700);
+        emulator.set_seed(42);
+        emulator.set_quirks(Platform::Vip.quirks());
+        let mut movie = Movie::new(&emulator);
+        for frame in 0..100 {
+            let keys: &[usize] = if (30..45).contains(&frame) { &[5] } else { &[] };
+            movie.record(frame, keypad(keys));
+        }
+        movie
+    }
+
+    #[test]
+    fn format_parse_round_trip() {
+        for mut movie in [recorded_movie(), Movie::new(&Chip8::new(1000))] {
+            movie.quirks = Platform::XoChip.quirks();
+            assert_eq!(Movie::parse(&movie.to_string()), Ok(movie.clone()));
+            movie.quirks = Quirks {
+                shift_vy: false,
+                memory_increment: false,
+                jump_vx: false,
+                vf_reset: false,
+                wrap_sprites: false,
+                display_wait: false,
+            };
+            assert_eq!(Movie::parse(&movie.to_string()), Ok(movie));
+        }
+    }
+
+    #[test]
+    fn formats_key_changes_only() {
+        let text = recorded_movie().to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], HEADER);
+        assert_eq!(
+            lines[2..],
+            [
+                "seed 42",
+                "ips 700",
+                "quirks shift_vy,memory_increment,vf_reset,display_wait",
+                "frames 100",
+                "30 0020",
+                "45 0000",
+            ]
+        );
+    }
+
+    #[test]
+    fn keypad_during_and_after() {
+        let movie = recorded_movie();
+        assert_eq!(movie.keypad(29), keypad(&[]));
+        assert_eq!(movie.keypad(30), keypad(&[5]));
+        assert_eq!(movie.keypad(44), keypad(&[5]));
+        assert_eq!(movie.keypad(45), keypad(&[]));
+
+        let mut held = Movie::parse("chip8-movie 1\nrom 0\nseed 0\nips 700\nquirks none\nframes 10\n0 ffff").unwrap();
+        assert_eq!(held.keypad(9), [true; 16]);
+        // every key is released once it's over
+        assert_eq!(held.keypad(10), [false; 16]);
+        held.truncate(5);
+        assert_eq!(held.frames, 5);
+        assert_eq!(held.keypad(5), [false; 16]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let valid = recorded_movie().to_string();
+        let errors = [
+            ("", "line 1: not a movie file"),
+            ("chip8-movie 2", "line 1: unsupported movie version, expected 'chip8-movie 1'"),
+            (&valid.replace("seed 42", "seed x"), "line 3: invalid seed"),
+            (&valid.replace("quirks ", "quirks warp,"), "line 5: unknown quirk 'warp'"),
+            (&valid.replace("45 0000", "20 0000"), "line 8: frames are out of order"),
+            (&valid.replace("ips 700\n", ""), "missing 'ips'"),
+        ];
+        for (text, message) in errors {
+            assert_eq!(Movie::parse(text), Err(message.to_string()));
+        }
+    }
+}
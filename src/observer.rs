@@ -0,0 +1,76 @@
+use crate::*;
+
+/// Hooks into execution one instruction at a time, for tracing and the like
+///
+/// See [`Chip8::step_observed`] and [`Chip8::run_frame_observed`]. Both
+/// methods do nothing by default, and `()` observes nothing at all.
+pub trait Observer {
+    /// Called before an instruction executes, with the PC pointing at it
+    fn before_step(&mut self, _emulator: &Chip8) {}
+
+    /// Called after an instruction executed, unless it failed
+    fn after_step(&mut self, _emulator: &Chip8, _result: &StepResult) {}
+
+    /// Called instead of [`Observer::after_step`] when an instruction failed,
+    /// with the PC still pointing at it
+    fn step_failed(&mut self, _emulator: &Chip8, _error: &Chip8Error) {}
+}
+
+impl Observer for () {}
+
+impl<T: Observer> Observer for Option<T> {
+    fn before_step(&mut self, emulator: &Chip8) {
+        if let Some(observer) = self {
+            observer.before_step(emulator);
+        }
+    }
+
+    fn after_step(&mut self, emulator: &Chip8, result: &StepResult) {
+        if let Some(observer) = self {
+            observer.after_step(emulator, result);
+        }
+    }
+
+    fn step_failed(&mut self, emulator: &Chip8, error: &Chip8Error) {
+        if let Some(observer) = self {
+            observer.step_failed(emulator, error);
+        }
+    }
+}
+
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn before_step(&mut self, emulator: &Chip8) {
+        self.0.before_step(emulator);
+        self.1.before_step(emulator);
+    }
+
+    fn after_step(&mut self, emulator: &Chip8, result: &StepResult) {
+        self.0.after_step(emulator, result);
+        self.1.after_step(emulator, result);
+    }
+
+    fn step_failed(&mut self, emulator: &Chip8, error: &Chip8Error) {
+        self.0.step_failed(emulator, error);
+        self.1.step_failed(emulator, error);
+    }
+}
+
+impl<A: Observer, B: Observer, C: Observer> Observer for (A, B, C) {
+    fn before_step(&mut self, emulator: &Chip8) {
+        self.0.before_step(emulator);
+        self.1.before_step(emulator);
+        self.2.before_step(emulator);
+    }
+
+    fn after_step(&mut self, emulator: &Chip8, result: &StepResult) {
+        self.0.after_step(emulator, result);
+        self.1.after_step(emulator, result);
+        self.2.after_step(emulator, result);
+    }
+
+    fn step_failed(&mut self, emulator: &Chip8, error: &Chip8Error) {
+        self.0.step_failed(emulator, error);
+        self.1.step_failed(emulator, error);
+        self.2.step_failed(emulator, error);
+    }
+}
@@ -0,0 +1,77 @@
+/// Colours indexed by pixel value (the bitmask of lit planes): off, lit on
+/// the first XO-CHIP plane only, on the second plane only, and on both
+pub type Palette = [[u8; 4]; 4];
+
+/// Built-in palettes for `--palette`, the first being the default
+pub const THEMES: [(&str, Palette); 5] = [
+    ("default", [[0x00, 0x0c, 0x1c, 0xff], [0xcd, 0xda, 0xff, 0xff], [0xff, 0x66, 0x33, 0xff], [0x66, 0x22, 0x11, 0xff]]),
+    // the VIP's TV, a green phosphor monitor
+    ("vip", [[0x06, 0x12, 0x08, 0xff], [0x3c, 0xff, 0x6e, 0xff], [0x1c, 0x80, 0x3a, 0xff], [0xb4, 0xff, 0xc8, 0xff]]),
+    ("amber", [[0x16, 0x0c, 0x00, 0xff], [0xff, 0xb0, 0x00, 0xff], [0x99, 0x55, 0x00, 0xff], [0xff, 0xe2, 0x8a, 0xff]]),
+    // the HP 48's greenish grey LCD
+    ("lcd", [[0xc4, 0xcf, 0xa1, 0xff], [0x2b, 0x33, 0x1f, 0xff], [0x7d, 0x8a, 0x5e, 0xff], [0x52, 0x5c, 0x3c, 0xff]]),
+    ("high-contrast", [[0x00, 0x00, 0x00, 0xff], [0xff, 0xff, 0xff, 0xff], [0xff, 0xff, 0x00, 0xff], [0x00, 0xff, 0xff, 0xff]]),
+];
+
+/// Parses `--palette`: the name of one of the [`THEMES`], or hex colours
+/// `OFF,ON[,PLANE2,BOTH]` like `000000,33ff66`, with the plane colours left
+/// out taken from the default theme
+pub fn parse_palette(s: &str) -> Result<Palette, String> {
+    if let Some((_, palette)) = THEMES.iter().find(|(name, _)| *name == s) {
+        return Ok(*palette);
+    }
+    let colors: Vec<&str> = s.split(',').map(str::trim).collect();
+    if colors.len() != 2 && colors.len() != 4 {
+        let names: Vec<&str> = THEMES.iter().map(|(name, _)| *name).collect();
+        return Err(format!("expected one of {} or 2 or 4 hex colours like 000000,33ff66", names.join(", ")));
+    }
+    let mut palette = THEMES[0].1;
+    for (slot, color) in palette.iter_mut().zip(colors) {
+        let digits = color.trim_start_matches('#');
+        let rgb = u32::from_str_radix(digits, 16)
+            .ok()
+            .filter(|_| digits.len() == 6 && digits.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("invalid colour '{color}', expected RRGGBB"))?;
+        let [_, r, g, b] = rgb.to_be_bytes();
+        *slot = [r, g, b, 0xff];
+    }
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn themes_by_name() {
+        for (name, palette) in THEMES {
+            assert_eq!(parse_palette(name), Ok(palette));
+        }
+        assert_eq!(parse_palette("default"), Ok(THEMES[0].1));
+    }
+
+    #[test]
+    fn hex_colours() {
+        let palette = parse_palette("000000, #33ff66").unwrap();
+        assert_eq!(palette[0], [0x00, 0x00, 0x00, 0xff]);
+        assert_eq!(palette[1], [0x33, 0xff, 0x66, 0xff]);
+        // the planes keep the default's colours
+        assert_eq!(palette[2..], THEMES[0].1[2..]);
+
+        let palette = parse_palette("010203,040506,070809,0A0b0C").unwrap();
+        assert_eq!(palette, [[1, 2, 3, 0xff], [4, 5, 6, 0xff], [7, 8, 9, 0xff], [10, 11, 12, 0xff]]);
+    }
+
+    #[test]
+    fn invalid_palettes() {
+        assert!(parse_palette("sepia").unwrap_err().starts_with("expected one of default, vip"));
+        assert!(parse_palette("000000,111111,222222").is_err());
+        // (signs included, which `from_str_radix` would take)
+        for color in ["fff", "00000g", "1234567", "+12345", "-12345"] {
+            assert_eq!(
+                parse_palette(&format!("000000,{color}")),
+                Err(format!("invalid colour '{color}', expected RRGGBB"))
+            );
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use crate::*;
+
+/// Entries shown in each section of the report
+const REPORT_ROWS: usize = 10;
+
+/// Counts where execution time goes, for tuning ROMs
+///
+/// Every executed instruction is counted by address and by opcode class
+/// (`DXYN`, `8XY4`, ...). Backward jumps mark loops, and the stack is
+/// followed to tell how long is spent inside each subroutine, including the
+/// subroutines it calls (inclusive) and not (exclusive).
+pub struct Profile {
+    /// Instructions executed so far
+    cycles: u64,
+    /// Executions of each address
+    hits: Vec<u64>,
+    /// Executions of each opcode class, see [`opcode_class`]
+    classes: BTreeMap<u16, u64>,
+    /// Times each backward jump was taken, by target and jump address
+    loops: BTreeMap<(u16, u16), u64>,
+    /// Entry addresses of the subroutines being executed, innermost last,
+    /// `None` where that's unknown
+    calls: Vec<Option<u16>>,
+    subroutines: BTreeMap<u16, Subroutine>,
+    /// PC and opcode of the instruction being executed
+    current: (u16, u16),
+}
+
+#[derive(Default)]
+struct Subroutine {
+    calls: u64,
+    inclusive: u64,
+    exclusive: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self {
+            cycles: 0,
+            hits: vec![0; MEM_SIZE],
+            classes: BTreeMap::new(),
+            loops: BTreeMap::new(),
+            calls: Vec::new(),
+            subroutines: BTreeMap::new(),
+            current: (0, 0),
+        }
+    }
+
+    /// Instructions executed so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Text report of the hottest spots, disassembled from the emulator's
+    /// memory
+    pub fn report(&self, emulator: &Chip8) -> String {
+        let memory = emulator.memory();
+        let share = |n: u64| 100.0 * n as f64 / self.cycles.max(1) as f64;
+        let disasm = |addr: u16| {
+            match Instruction::decode_at(&memory[addr as usize..]) {
+                Some(instruction) => instruction.to_string(),
+                None => "???".to_string(),
+            }
+        };
+        let mut report = String::new();
+        let _ = writeln!(report, "Profile of {} instructions", self.cycles);
+
+        let _ = writeln!(report, "\nOpcode classes");
+        let mut classes: Vec<(&u16, &u64)> = self.classes.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1));
+        for (&class, &count) in classes {
+            let _ = writeln!(report, "  {:<6}  {count:>12}  {:>5.1}%", class_name(class), share(count));
+        }
+
+        let _ = writeln!(report, "\nHottest addresses");
+        let mut addrs: Vec<usize> = (0..MEM_SIZE).filter(|&addr| self.hits[addr] > 0).collect();
+        addrs.sort_by(|&a, &b| self.hits[b].cmp(&self.hits[a]));
+        for &addr in addrs.iter().take(REPORT_ROWS) {
+            let count = self.hits[addr];
+            let _ = writeln!(report, "  {addr:#05X}  {count:>12}  {:>5.1}%  {}", share(count), disasm(addr as u16));
+        }
+
+        let _ = writeln!(report, "\nHottest loops");
+        let mut loops: Vec<(RangeInclusive<u16>, u64, u64)> = self.loops
+            .iter()
+            .map(|(&(start, end), &iterations)| (start..=end, iterations, self.hits_in(start..=end)))
+            .collect();
+        loops.sort_by_key(|&(_, _, count)| std::cmp::Reverse(count));
+        if loops.is_empty() {
+            let _ = writeln!(report, "  none");
+        }
+        for (range, iterations, count) in loops.iter().take(REPORT_ROWS) {
+            let _ = writeln!(
+                report,
+                "  {:#05X}-{:#05X}  {iterations:>10} iterations  {:>5.1}%",
+                range.start(),
+                range.end(),
+                share(*count)
+            );
+        }
+
+        let timer_waits = self.timer_wait_loops(memory);
+        let timer_wait_count: u64 = timer_waits
+            .iter()
+            .map(|range| self.hits_in(range.clone()))
+            .sum();
+        let _ = writeln!(report, "\nTimer waits");
+        let _ = writeln!(
+            report,
+            "  {:.1}% of the time spent spinning on the delay timer (FX07 loops)",
+            share(timer_wait_count)
+        );
+        for range in timer_waits.iter() {
+            let _ = writeln!(
+                report,
+                "  {:#05X}-{:#05X}  {:>5.1}%",
+                range.start(),
+                range.end(),
+                share(self.hits_in(range.clone()))
+            );
+        }
+
+        let _ = writeln!(report, "\nSubroutines");
+        let mut subroutines: Vec<(&u16, &Subroutine)> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(_, subroutine)| std::cmp::Reverse(subroutine.inclusive));
+        if subroutines.is_empty() {
+            let _ = writeln!(report, "  none");
+        } else {
+            let _ = writeln!(report, "  entry         calls  inclusive  exclusive");
+        }
+        for (&entry, subroutine) in subroutines.iter().take(REPORT_ROWS) {
+            let _ = writeln!(
+                report,
+                "  {entry:#05X}  {:>12}  {:>8.1}%  {:>8.1}%",
+                subroutine.calls,
+                share(subroutine.inclusive),
+                share(subroutine.exclusive)
+            );
+        }
+        report
+    }
+
+    fn hits_in(&self, range: RangeInclusive<u16>) -> u64 {
+        self.hits[*range.start() as usize..=*range.end() as usize].iter().sum()
+    }
+
+    /// Finds the executed loops that do nothing but wait for the delay
+    /// timer to run out:
+    ///
+    /// ```text
+    /// wait:
+    ///     LD VX, DT     ; FX07
+    ///     SE VX, 0      ; 3X00 (or SNE VX, 0 and another jump)
+    ///     JP wait
+    /// ```
+    fn timer_wait_loops(&self, memory: &[u8]) -> Vec<RangeInclusive<u16>> {
+        let word = |addr: usize| u16::from_be_bytes([memory[addr % MEM_SIZE], memory[(addr + 1) % MEM_SIZE]]);
+        let mut loops = Vec::new();
+        for addr in (0..MEM_SIZE).filter(|&addr| self.hits[addr] > 0) {
+            let opcode = word(addr);
+            if opcode & 0xf0ff != 0xf007 {
+                continue;
+            }
+            let x = opcode & 0x0f00;
+            let test = word(addr + 2);
+            if test != 0x3000 | x && test != 0x4000 | x {
+                continue;
+            }
+            // the jump back, right after the test or one further on
+            let jump_back = 0x1000 | addr as u16;
+            if let Some(end) = [addr + 4, addr + 6].into_iter().find(|&end| word(end) == jump_back) {
+                loops.push(addr as u16..=end as u16);
+            }
+        }
+        loops
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Observer for Profile {
+    fn before_step(&mut self, emulator: &Chip8) {
+        let pc = emulator.pc();
+        let memory = emulator.memory();
+        let opcode = u16::from_be_bytes([memory[pc as usize], memory[pc.wrapping_add(1) as usize]]);
+        self.current = (pc, opcode);
+        self.cycles += 1;
+        self.hits[pc as usize] += 1;
+        *self.classes.entry(opcode_class(opcode)).or_default() += 1;
+
+        // recursive calls count once towards inclusive time
+        for (i, entry) in self.calls.iter().enumerate() {
+            if let Some(entry) = entry.filter(|entry| !self.calls[..i].contains(&Some(*entry))) {
+                self.subroutines.entry(entry).or_default().inclusive += 1;
+            }
+        }
+        if let Some(&Some(entry)) = self.calls.last() {
+            self.subroutines.entry(entry).or_default().exclusive += 1;
+        }
+    }
+
+    fn after_step(&mut self, emulator: &Chip8, _result: &StepResult) {
+        let (pc, opcode) = self.current;
+        let depth = emulator.stack().len();
+        if opcode & 0xf000 == 0x2000 && depth > self.calls.len() {
+            self.calls.push(Some(emulator.pc()));
+            self.subroutines.entry(emulator.pc()).or_default().calls += 1;
+        } else if opcode & 0xf000 == 0x1000 && emulator.pc() <= pc {
+            *self.loops.entry((emulator.pc(), pc)).or_default() += 1;
+        }
+        // anything else moving the stack (loading a state, say) loses track
+        // of which subroutines are running
+        self.calls.truncate(depth);
+        while self.calls.len() < depth {
+            self.calls.insert(0, None);
+        }
+    }
+}
+
+/// Opcode with the operands masked out, telling apart the instructions
+fn opcode_class(opcode: u16) -> u16 {
+    match opcode >> 12 {
+        0x0 => {
+            match opcode & 0xfff0 {
+                0x00c0 | 0x00d0 => opcode & 0xfff0,
+                _ if opcode & 0xff00 == 0 => opcode,
+                _ => 0x0000,
+            }
+        }
+        0x5 | 0x8 | 0x9 => opcode & 0xf00f,
+        0xe | 0xf if opcode == 0xf000 => opcode,
+        0xe | 0xf => opcode & 0xf0ff,
+        _ => opcode & 0xf000,
+    }
+}
+
+/// Name of an opcode class, like `8XY4`
+fn class_name(class: u16) -> String {
+    let n = class >> 12;
+    match n {
+        0x0 if class == 0 => "0NNN".to_string(),
+        0x0 if matches!(class & 0xfff0, 0x00c0 | 0x00d0) => format!("00{:X}N", (class >> 4) & 0xf),
+        0x1 | 0x2 | 0xa | 0xb => format!("{n:X}NNN"),
+        0x3 | 0x4 | 0x6 | 0x7 | 0xc => format!("{n:X}XNN"),
+        0x5 | 0x8 | 0x9 => format!("{n:X}XY{:X}", class & 0xf),
+        0xd => "DXYN".to_string(),
+        0xf if class == 0xf000 => "F000".to_string(),
+        0xe | 0xf => format!("{n:X}X{:02X}", class & 0xff),
+        _ => format!("{class:04X}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::boot;
+
+    /// LD V0, 3, LD DT, V0, a delay timer wait at 0x204, CALL 0x20E, then
+    /// JP 0x20C forever, and a subroutine calling another:
+    /// ADD V2, 1, CALL 0x214, RET, RET
+    const ROM: [u8; 22] = [
+        0x60, 0x03, 0xf0, 0x15, 0xf1, 0x07, 0x31, 0x00, 0x12, 0x04, 0x22, 0x0e, 0x12, 0x0c, 0x72,
+        0x01, 0x22, 0x14, 0x00, 0xee, 0x00, 0xee,
+    ];
+
+    /// Runs `frames` frames of 3 instructions
+    fn profile(emulator: &mut Chip8, frames: usize) -> Profile {
+        let mut profile = Profile::new();
+        for _ in 0..frames {
+            emulator.run_cycles_observed(3, &mut profile).unwrap();
+            emulator.tick_timers();
+        }
+        profile
+    }
+
+    fn subroutine(profile: &Profile, entry: u16) -> (u64, u64, u64) {
+        let subroutine = &profile.subroutines[&entry];
+        (subroutine.calls, subroutine.inclusive, subroutine.exclusive)
+    }
+
+    #[test]
+    fn loops_and_timer_waits() {
+        let mut emulator = boot(700, &ROM);
+        let profile = profile(&mut emulator, 7);
+        assert_eq!(profile.cycles(), 21);
+        assert_eq!(profile.hits[0x204..0x20a].iter().step_by(2).collect::<Vec<_>>(), [&4, &4, &3]);
+        assert_eq!(profile.loops, BTreeMap::from([((0x204, 0x208), 3), ((0x20c, 0x20c), 3)]));
+        assert_eq!(profile.timer_wait_loops(emulator.memory()), [0x204..=0x208]);
+        assert_eq!(profile.classes[&0xf007], 4);
+
+        let report = profile.report(&emulator);
+        for line in [
+            "Profile of 21 instructions",
+            "  FX07               4   19.0%",
+            "  0x204-0x208           3 iterations   52.4%",
+            "  52.4% of the time spent spinning on the delay timer (FX07 loops)",
+            "  0x20E             1      19.0%      14.3%",
+        ] {
+            assert!(report.lines().any(|report_line| report_line == line), "{line}\n{report}");
+        }
+    }
+
+    #[test]
+    fn subroutines_inclusive_and_exclusive() {
+        let mut emulator = boot(700, &ROM);
+        let profile = profile(&mut emulator, 7);
+        // ADD, CALL and RET itself, plus the RET of the subroutine it calls
+        assert_eq!(subroutine(&profile, 0x20e), (1, 4, 3));
+        assert_eq!(subroutine(&profile, 0x214), (1, 1, 1));
+        assert!(profile.calls.is_empty());
+    }
+
+    #[test]
+    fn recursion_counts_once() {
+        // CALL 0x204, JP 0x202, then a subroutine calling itself until V0 is 3:
+        // ADD V0, 1, SE V0, 3, CALL 0x204, RET
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x70, 0x01, 0x30, 0x03, 0x22, 0x04, 0x00, 0xee];
+        let mut emulator = boot(700, &rom);
+        let mut profile = Profile::new();
+        emulator.run_cycles_observed(13, &mut profile).unwrap();
+        assert_eq!(emulator.pc(), 0x202);
+        // 11 instructions in it, however deep
+        assert_eq!(subroutine(&profile, 0x204), (3, 11, 11));
+    }
+
+    #[test]
+    fn calls_follow_the_stack_moving() {
+        let mut emulator = boot(700, &ROM);
+        let mut profile = profile(&mut emulator, 5);
+        assert_eq!((emulator.pc(), profile.calls.as_slice()), (0x210, [Some(0x20e)].as_slice()));
+
+        // say, a state from outside any subroutine is loaded
+        emulator.stack.clear();
+        emulator.pc = 0x20c;
+        emulator.step_observed(&mut profile).unwrap();
+        assert!(profile.calls.is_empty());
+
+        // or one from two calls deep
+        emulator.stack = vec![0x20c, 0x20c];
+        emulator.step_observed(&mut profile).unwrap();
+        assert_eq!(profile.calls, [None, None]);
+        let before = subroutine(&profile, 0x20e);
+        emulator.run_cycles_observed(10, &mut profile).unwrap();
+        assert_eq!(subroutine(&profile, 0x20e), before);
+    }
+}
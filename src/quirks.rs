@@ -0,0 +1,224 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Behaviours that differ between CHIP-8 interpreters
+///
+/// ROMs tend to rely on whatever the interpreter they were written for did,
+/// so these are usually picked as a whole through a [`Platform`] preset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX, instead of shifting VX in place
+    pub shift_vy: bool,
+    /// FX55/FX65 leave I pointing after the last register (I += X + 1),
+    /// instead of leaving it unchanged
+    pub memory_increment: bool,
+    /// BNNN jumps to XNN + VX, instead of NNN + V0
+    pub jump_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// Sprites wrap around to the other side of the screen, instead of being
+    /// clipped at the edges
+    pub wrap_sprites: bool,
+    /// DXYN waits for the next 60hz tick before drawing, so at most one
+    /// sprite is drawn per frame
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub const VIP: Quirks = Quirks {
+        shift_vy: true,
+        memory_increment: true,
+        jump_vx: false,
+        vf_reset: true,
+        wrap_sprites: false,
+        display_wait: true,
+    };
+
+    /// CHIP-48 on the HP-48 calculators, which most 90s ROMs were written for
+    ///
+    /// SUPER-CHIP 1.1 kept all of these, so it shares the preset.
+    pub const CHIP48: Quirks = Quirks {
+        shift_vy: false,
+        memory_increment: false,
+        jump_vx: true,
+        vf_reset: false,
+        wrap_sprites: false,
+        display_wait: false,
+    };
+
+    /// XO-CHIP, as implemented by Octo
+    pub const XOCHIP: Quirks = Quirks {
+        shift_vy: true,
+        memory_increment: true,
+        jump_vx: false,
+        vf_reset: false,
+        wrap_sprites: true,
+        display_wait: false,
+    };
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Platform::default().quirks()
+    }
+}
+
+/// Named interpreter that a ROM was written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    Vip,
+    /// CHIP-48, and SUPER-CHIP 1.1 with the same quirks
+    #[default]
+    Chip48,
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 3] = [Platform::Vip, Platform::Chip48, Platform::XoChip];
+
+    /// Other names accepted by `FromStr`
+    const ALIASES: [(&'static str, Platform); 1] = [("schip", Platform::Chip48)];
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Vip => Quirks::VIP,
+            Platform::Chip48 => Quirks::CHIP48,
+            Platform::XoChip => Quirks::XOCHIP,
+        }
+    }
+
+    /// Short name, as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Vip => "vip",
+            Platform::Chip48 => "chip48",
+            Platform::XoChip => "xochip",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase().replace(['-', '_'], "");
+        Platform::ALL
+            .into_iter()
+            .map(|platform| (platform.name(), platform))
+            .chain(Platform::ALIASES)
+            .find(|(name, _)| *name == s)
+            .map(|(_, platform)| platform)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Platform::ALL
+                    .iter()
+                    .map(|platform| platform.name())
+                    .chain(Platform::ALIASES.map(|(name, _)| name))
+                    .collect();
+                format!("unknown platform, expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8;
+
+    /// Runs the first `steps` instructions of `rom` under every preset
+    fn run_presets(rom: &[u8], steps: usize) -> Vec<(Quirks, Chip8)> {
+        Platform::ALL
+            .iter()
+            .map(|platform| {
+                let mut emulator = Chip8::new(700);
+                emulator.set_quirks(platform.quirks());
+                emulator.load_rom_bytes(rom).unwrap();
+                for _ in 0..steps {
+                    emulator.step().unwrap();
+                }
+                (platform.quirks(), emulator)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shift_vy() {
+        // LD V0, 0x10, LD V1, 0x03, SHR V0, V1
+        for (quirks, emulator) in run_presets(&[0x60, 0x10, 0x61, 0x03, 0x80, 0x16], 3) {
+            let expected = if quirks.shift_vy { 0x01 } else { 0x08 };
+            assert_eq!(emulator.V[0], expected, "{quirks:?}");
+        }
+    }
+
+    #[test]
+    fn memory_increment() {
+        // LD I, 0x300, LD [I], V1
+        for (quirks, emulator) in run_presets(&[0xa3, 0x00, 0xf1, 0x55], 2) {
+            let expected = if quirks.memory_increment { 0x302 } else { 0x300 };
+            assert_eq!(emulator.I, expected, "{quirks:?}");
+        }
+    }
+
+    #[test]
+    fn jump_vx() {
+        // LD V2, 4, LD V0, 1, JP V0, 0x210
+        for (quirks, emulator) in run_presets(&[0x62, 0x04, 0x60, 0x01, 0xb2, 0x10], 3) {
+            let expected = if quirks.jump_vx { 0x214 } else { 0x211 };
+            assert_eq!(emulator.pc, expected, "{quirks:?}");
+        }
+    }
+
+    #[test]
+    fn vf_reset() {
+        // LD VF, 5, OR V0, V1
+        for (quirks, emulator) in run_presets(&[0x6f, 0x05, 0x80, 0x11], 2) {
+            let expected = if quirks.vf_reset { 0 } else { 5 };
+            assert_eq!(emulator.V[0xf], expected, "{quirks:?}");
+        }
+    }
+
+    #[test]
+    fn wrap_sprites() {
+        // LD V0, 63, LD F, V1 (the 8 pixel wide "0"), DRW V0, V1, 5
+        for (quirks, emulator) in run_presets(&[0x60, 0x3f, 0xf1, 0x29, 0xd0, 0x15], 3) {
+            assert_ne!(emulator.pixel_buf[0][63], 0, "{quirks:?}");
+            assert_eq!(emulator.pixel_buf[0][0] != 0, quirks.wrap_sprites, "{quirks:?}");
+        }
+    }
+
+    #[test]
+    fn display_wait() {
+        // DRW V0, V0, 1 twice in one frame
+        for (quirks, mut emulator) in run_presets(&[0xd0, 0x01, 0xd0, 0x01], 1) {
+            let result = emulator.step().unwrap();
+            assert_eq!(result.waiting_for_display, quirks.display_wait, "{quirks:?}");
+            if result.waiting_for_display {
+                // drawn once the next frame starts
+                assert_eq!(emulator.pc, 0x202);
+                emulator.tick_timers();
+                emulator.step().unwrap();
+            }
+            assert_eq!(emulator.pc, 0x204, "{quirks:?}");
+        }
+    }
+
+    #[test]
+    fn platform_names_parse() {
+        for platform in Platform::ALL {
+            assert_eq!(platform.name().parse::<Platform>(), Ok(platform));
+        }
+        assert_eq!("XO-CHIP".parse::<Platform>(), Ok(Platform::XoChip));
+        assert_eq!("SCHIP".parse::<Platform>(), Ok(Platform::Chip48));
+        assert_eq!(
+            "chip9".parse::<Platform>(),
+            Err("unknown platform, expected one of: vip, chip48, xochip, schip".to_string())
+        );
+        assert_eq!(Quirks::default(), Quirks::CHIP48);
+    }
+}
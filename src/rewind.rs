@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+
+use crate::{ Chip8, Chip8Error };
+
+/// Ring buffer of past machine states, for stepping back in time
+///
+/// Only the newest state is kept whole. Every older one is stored as the
+/// difference to the state after it, run length encoded, and since a frame
+/// usually only touches a few bytes of memory and the screen those are tiny.
+/// The oldest states are dropped once there are more than `max_frames` of
+/// them or they take more than `max_bytes`.
+pub struct Rewind {
+    /// Newest state, as made by [`Chip8::save_snapshot`]
+    latest: Option<Vec<u8>>,
+    /// The buffer of a replaced state, reused for the next snapshot
+    scratch: Vec<u8>,
+    /// Deltas that each turn a state into the one before it, oldest first
+    deltas: VecDeque<Vec<u8>>,
+    /// Total size of `deltas`
+    delta_bytes: usize,
+    max_frames: usize,
+    max_bytes: usize,
+}
+
+impl Rewind {
+    pub fn new(max_frames: usize, max_bytes: usize) -> Self {
+        Self {
+            latest: None,
+            scratch: Vec::new(),
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            max_frames,
+            max_bytes,
+        }
+    }
+
+    /// Number of frames that can be stepped back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Memory used by the buffer, in bytes
+    pub fn size(&self) -> usize {
+        self.delta_bytes + self.latest.as_ref().map_or(0, |latest| latest.len())
+    }
+
+    /// Forgets every recorded state
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    /// Records the current state of the emulator, normally once per frame
+    pub fn record(&mut self, emulator: &Chip8) {
+        if self.max_frames == 0 {
+            return;
+        }
+        let mut state = std::mem::take(&mut self.scratch);
+        emulator.save_snapshot(&mut state);
+        if let Some(latest) = self.latest.take() {
+            let delta = encode_delta(&state, &latest);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+            self.scratch = latest;
+        }
+        self.latest = Some(state);
+
+        while self.deltas.len() > self.max_frames || self.size() > self.max_bytes {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Restores the emulator to the frame before the last recorded one,
+    /// returning false once there's nothing older left
+    pub fn step_back(&mut self, emulator: &mut Chip8) -> bool {
+        let (Some(latest), Some(delta)) = (self.latest.as_ref(), self.deltas.pop_back()) else {
+            return false;
+        };
+        self.delta_bytes -= delta.len();
+        let restored = apply_delta(latest, &delta).and_then(|previous| {
+            emulator.load_snapshot(&previous)?;
+            Ok(previous)
+        });
+        match restored {
+            Ok(previous) => {
+                self.scratch = self.latest.replace(previous).unwrap_or_default();
+                true
+            }
+            Err(_) => {
+                // only ever holds states the emulator made itself
+                self.clear();
+                false
+            }
+        }
+    }
+}
+
+/// Encodes `to` as the difference from `from`
+///
+/// The layout is the length of `to`, followed by runs of unchanged bytes
+/// (a varint count) alternating with runs of changed ones (a varint count and
+/// the XOR of the bytes).
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let xor = |i: usize| from.get(i).unwrap_or(&0) ^ to.get(i).unwrap_or(&0);
+
+    let mut delta = Vec::new();
+    push_varint(&mut delta, to.len());
+    let mut i = 0;
+    while i < len {
+        let same = (i..len).take_while(|&j| xor(j) == 0).count();
+        i += same;
+        let changed = (i..len).take_while(|&j| xor(j) != 0).count();
+        push_varint(&mut delta, same);
+        push_varint(&mut delta, changed);
+        delta.extend((i..i + changed).map(xor));
+        i += changed;
+    }
+    delta
+}
+
+/// Rebuilds the state that `delta` was encoded against `from` from, failing
+/// if the delta is cut short or doesn't fit
+fn apply_delta(from: &[u8], delta: &[u8]) -> Result<Vec<u8>, Chip8Error> {
+    let truncated = || Chip8Error::InvalidState("truncated".to_string());
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos)?;
+    let mut to = from.to_vec();
+    to.resize(len.max(from.len()), 0);
+
+    let mut i: usize = 0;
+    while pos < delta.len() {
+        i = i.checked_add(read_varint(delta, &mut pos)?).ok_or_else(truncated)?;
+        let changed = read_varint(delta, &mut pos)?;
+        let bytes = delta.get(pos..pos.saturating_add(changed)).ok_or_else(truncated)?;
+        let target = to.get_mut(i..i.saturating_add(changed)).ok_or_else(truncated)?;
+        for (to, byte) in target.iter_mut().zip(bytes) {
+            *to ^= byte;
+        }
+        i += changed;
+        pos += changed;
+    }
+    to.truncate(len);
+    Ok(to)
+}
+
+/// LEB128, 7 bits at a time, lowest first
+fn push_varint(buf: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<usize, Chip8Error> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let Some(&byte) = buf.get(*pos) else {
+            return Err(Chip8Error::InvalidState("truncated".to_string()));
+        };
+        if shift >= usize::BITS {
+            return Err(Chip8Error::InvalidState("varint too long".to_string()));
+        }
+        *pos += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_round_trip() {
+        let from = [1, 2, 3, 4, 5, 6, 7, 8];
+        for to in [
+            vec![1, 2, 3, 4, 5, 6, 7, 8],
+            vec![1, 9, 3, 4, 0, 0, 7, 9],
+            vec![1, 2, 3],
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
+            vec![],
+        ] {
+            let delta = encode_delta(&from, &to);
+            assert_eq!(apply_delta(&from, &delta).unwrap(), to);
+        }
+    }
+
+    #[test]
+    fn unchanged_runs_are_small() {
+        let from = vec![0xaa; 70000];
+        let mut to = from.clone();
+        to[200] = 0;
+        to[65000] = 0;
+        // two changed bytes take a handful of varints, not 70 KB
+        assert!(encode_delta(&from, &to).len() < 16);
+    }
+
+    #[test]
+    fn rejects_truncated_deltas() {
+        let from = [1, 2, 3, 4];
+        // the length, 1 unchanged, 2 changed, then the trailing unchanged run
+        let delta = encode_delta(&from, &[1, 5, 6, 4]);
+        assert_eq!(delta, [4, 1, 2, 2 ^ 5, 3 ^ 6, 1, 0]);
+        // cut inside a run, rather than between two
+        for len in [0, 2, 3, 4, 6] {
+            assert!(apply_delta(&from, &delta[..len]).is_err(), "{len} bytes");
+        }
+        // a run past the end of the state
+        assert!(apply_delta(&from, &[4, 2, 5, 1, 1, 1, 1, 1]).is_err());
+        assert!(apply_delta(&from, &[0x80; 12]).is_err());
+    }
+
+    #[test]
+    fn steps_back_through_recorded_frames() {
+        let mut emulator = crate::tests::example("snake");
+        let mut rewind = Rewind::new(10, usize::MAX);
+        let mut states = Vec::new();
+        for _ in 0..20 {
+            emulator.run_frame().unwrap();
+            rewind.record(&emulator);
+            states.push(emulator.save_state());
+        }
+        assert_eq!(rewind.len(), 10);
+
+        for state in states[9..19].iter().rev() {
+            assert!(rewind.step_back(&mut emulator));
+            assert_eq!(&emulator.save_state(), state);
+        }
+        assert!(!rewind.step_back(&mut emulator));
+        assert!(rewind.is_empty());
+    }
+}
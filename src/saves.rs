@@ -0,0 +1,169 @@
+use std::{ env, fs, io };
+use std::path::PathBuf;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::*;
+
+/// Per-user directory for files that outlive a run, like the RPL flags and
+/// save states
+///
+/// `$XDG_DATA_HOME/chip8` (or `~/.local/share/chip8`), `%APPDATA%\chip8` on
+/// Windows.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else {
+        match env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+        }
+    };
+    Some(base.join("chip8"))
+}
+
+/// Per-user directory for settings files, like `keymap.conf`
+///
+/// `$XDG_CONFIG_HOME/chip8` (or `~/.config/chip8`), `%APPDATA%\chip8` on
+/// Windows.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else {
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(base.join("chip8"))
+}
+
+fn rpl_path(rom_hash: u64) -> Option<PathBuf> {
+    Some(data_dir()?.join("rpl").join(format!("{rom_hash:016x}.rpl")))
+}
+
+/// RPL user flags saved by an earlier run of the ROM, if any
+pub fn load_rpl_flags(rom_hash: u64) -> Option<[u8; 16]> {
+    let path = rpl_path(rom_hash)?;
+    let bytes = fs::read(&path).ok()?;
+    println_debug!("Loaded RPL flags from {}", path.display());
+    bytes.try_into().ok()
+}
+
+/// Saves the RPL user flags so the next run of the ROM gets them back
+pub fn save_rpl_flags(rom_hash: u64, flags: &[u8; 16]) -> io::Result<()> {
+    let path = rpl_path(rom_hash).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no user data directory")
+    })?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, flags)
+}
+
+fn state_path(rom_hash: u64, slot: u8) -> Option<PathBuf> {
+    Some(data_dir()?.join("states").join(format!("{rom_hash:016x}.{slot}.state")))
+}
+
+/// Writes a save state to the ROM's numbered slot
+pub fn save_state(rom_hash: u64, slot: u8, state: &[u8]) -> io::Result<PathBuf> {
+    let path = state_path(rom_hash, slot).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no user data directory")
+    })?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, state)?;
+    Ok(path)
+}
+
+/// Reads the save state in the ROM's numbered slot
+pub fn load_state(rom_hash: u64, slot: u8) -> io::Result<Vec<u8>> {
+    let path = state_path(rom_hash, slot).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no user data directory")
+    })?;
+    fs::read(path)
+}
+
+/// `<prefix>-YYYYMMDD-HHMMSS.<extension>` in the current directory, from the
+/// current (UTC) time, with a counter added if that's taken
+pub fn timestamped_path(prefix: &str, extension: &str) -> PathBuf {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    let stamp = format!(
+        "{prefix}-{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    );
+
+    let mut path = PathBuf::from(format!("{stamp}.{extension}"));
+    let mut n = 2;
+    while path.exists() {
+        path = PathBuf::from(format!("{stamp}-{n}.{extension}"));
+        n += 1;
+    }
+    path
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date
+///
+/// Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the only test touching the environment, so it can't race with another
+    #[test]
+    #[cfg(not(windows))]
+    fn saves_persist_in_the_data_dir() {
+        let dir = env::temp_dir().join(format!("chip8-saves-{}", std::process::id()));
+        env::set_var("XDG_DATA_HOME", &dir);
+        assert_eq!(data_dir(), Some(dir.join("chip8")));
+
+        let hash = rom_hash(b"test");
+        assert_eq!(load_rpl_flags(hash), None);
+        let flags = std::array::from_fn(|i| i as u8);
+        save_rpl_flags(hash, &flags).unwrap();
+        assert_eq!(load_rpl_flags(hash), Some(flags));
+        assert!(dir.join("chip8").join("rpl").join(format!("{hash:016x}.rpl")).is_file());
+        assert_eq!(load_rpl_flags(rom_hash(b"other")), None);
+
+        // files of the wrong size are ignored
+        fs::write(rpl_path(hash).unwrap(), [1, 2, 3]).unwrap();
+        assert_eq!(load_rpl_flags(hash), None);
+
+        let path = save_state(hash, 3, b"state").unwrap();
+        assert_eq!(path, dir.join("chip8").join("states").join(format!("{hash:016x}.3.state")));
+        assert_eq!(load_state(hash, 3).unwrap(), b"state");
+        assert_eq!(load_state(hash, 4).unwrap_err().kind(), io::ErrorKind::NotFound);
+        fs::remove_dir_all(&dir).unwrap();
+
+        env::set_var("XDG_DATA_HOME", "");
+        let home = PathBuf::from(env::var_os("HOME").unwrap());
+        assert_eq!(data_dir(), Some(home.join(".local").join("share").join("chip8")));
+
+        env::set_var("XDG_CONFIG_HOME", &dir);
+        assert_eq!(config_dir(), Some(dir.join("chip8")));
+        env::set_var("XDG_CONFIG_HOME", "");
+        assert_eq!(config_dir(), Some(home.join(".config").join("chip8")));
+    }
+
+    #[test]
+    fn dates_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19723), (2024, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+}
@@ -0,0 +1,314 @@
+use crate::*;
+
+/// Identifies a save state file
+const MAGIC: [u8; 4] = *b"C8ST";
+/// Bumped whenever the layout of the payload changes
+const VERSION: u16 = 1;
+/// Magic, version, payload length and checksum
+const HEADER_SIZE: usize = 4 + 2 + 4 + 4;
+
+impl Chip8 {
+    /// Serializes the complete machine state
+    ///
+    /// The format is a header (magic, version, payload length and CRC-32 of
+    /// the payload, all little endian) followed by the payload. The ROM is
+    /// part of memory, so a state restores fine without it being loaded.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        self.save_snapshot(&mut payload);
+
+        let mut state = Vec::with_capacity(HEADER_SIZE + payload.len());
+        state.extend_from_slice(&MAGIC);
+        state.extend_from_slice(&VERSION.to_le_bytes());
+        state.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        state.extend_from_slice(&crc32(&payload).to_le_bytes());
+        state.extend_from_slice(&payload);
+        state
+    }
+
+    /// Serializes the machine into `payload`, replacing its contents, without
+    /// the header or checksum of [`Chip8::save_state`]
+    ///
+    /// Cheap enough to run every frame, see [`Rewind`].
+    pub(crate) fn save_snapshot(&self, payload: &mut Vec<u8>) {
+        payload.clear();
+        payload.reserve(MEM_SIZE + HIRES_SCREEN_W * HIRES_SCREEN_H + 128);
+        payload.extend_from_slice(&self.memory);
+        payload.extend_from_slice(&self.V);
+        payload.extend_from_slice(&self.I.to_le_bytes());
+        payload.extend_from_slice(&self.pc.to_le_bytes());
+        payload.push(self.stack.len() as u8);
+        for addr in self.stack.iter() {
+            payload.extend_from_slice(&addr.to_le_bytes());
+        }
+        payload.push(self.delay_t);
+        payload.push(self.sound_t);
+
+        // display
+        for row in self.pixel_buf.iter() {
+            payload.extend_from_slice(row);
+        }
+        payload.push(self.hires as u8);
+        payload.push(self.planes);
+
+        payload.extend_from_slice(&self.seed.to_le_bytes());
+        payload.extend_from_slice(&self.rng.state().to_le_bytes());
+
+        let quirks = [
+            self.quirks.shift_vy,
+            self.quirks.memory_increment,
+            self.quirks.jump_vx,
+            self.quirks.vf_reset,
+            self.quirks.wrap_sprites,
+            self.quirks.display_wait,
+        ];
+        payload.extend(quirks.iter().map(|&quirk| quirk as u8));
+
+        // everything else needed to resume mid-frame
+        for key in self.keypad {
+            payload.push(key as u8);
+        }
+        payload.extend_from_slice(&self.key_releases.to_le_bytes());
+        payload.push(self.waiting_for_key as u8);
+        payload.extend_from_slice(&(self.cycle_carry as u32).to_le_bytes());
+        payload.push(self.vblank as u8);
+        payload.push(self.waiting_for_display as u8);
+        payload.extend_from_slice(&self.rpl);
+        payload.push(self.exited as u8);
+        payload.extend_from_slice(&self.rom_hash.to_le_bytes());
+        match self.audio_pattern {
+            Some(pattern) => {
+                payload.push(1);
+                payload.extend_from_slice(&pattern);
+            }
+            None => payload.push(0),
+        }
+        payload.push(self.pitch);
+    }
+
+    /// Restores a state made by [`Chip8::save_state`]
+    ///
+    /// The state is fully validated first, so on error the machine is left
+    /// untouched. Instructions per second aren't part of the state.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        let invalid = |why: &str| Chip8Error::InvalidState(why.to_string());
+        if state.len() < HEADER_SIZE || state[0..4] != MAGIC {
+            return Err(invalid("not a save state"));
+        }
+        let version = u16::from_le_bytes([state[4], state[5]]);
+        if version != VERSION {
+            return Err(Chip8Error::InvalidState(format!("unsupported version {version}, expected {VERSION}")));
+        }
+        let len = u32::from_le_bytes(state[6..10].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(state[10..14].try_into().unwrap());
+        let payload = &state[HEADER_SIZE..];
+        if payload.len() != len {
+            return Err(invalid("truncated"));
+        }
+        if crc32(payload) != checksum {
+            return Err(invalid("checksum mismatch"));
+        }
+        self.load_snapshot(payload)
+    }
+
+    /// Restores a payload made by [`Chip8::save_snapshot`], leaving the
+    /// machine untouched on error
+    pub(crate) fn load_snapshot(&mut self, payload: &[u8]) -> Result<(), Chip8Error> {
+        let invalid = |why: &str| Chip8Error::InvalidState(why.to_string());
+        let mut reader = Reader { data: payload, pos: 0 };
+        // every field that's part of the state gets overwritten
+        let mut new = self.clone();
+        new.stack.clear();
+        new.memory.copy_from_slice(reader.bytes(MEM_SIZE)?);
+        new.V.copy_from_slice(reader.bytes(N_REGISTERS)?);
+        new.I = reader.u16()?;
+        new.pc = reader.u16()?;
+        let stack_len = reader.u8()? as usize;
+        if stack_len > STACK_SIZE {
+            return Err(invalid("stack too deep"));
+        }
+        for _ in 0..stack_len {
+            new.stack.push(reader.u16()?);
+        }
+        new.delay_t = reader.u8()?;
+        new.sound_t = reader.u8()?;
+
+        // display
+        for row in new.pixel_buf.iter_mut() {
+            row.copy_from_slice(reader.bytes(HIRES_SCREEN_W)?);
+        }
+        new.hires = reader.bool()?;
+        new.planes = reader.u8()?;
+        new.pixel_buf_updated = true;
+
+        new.seed = reader.u64()?;
+        new.rng = Rng::new(reader.u64()?);
+
+        new.quirks = Quirks {
+            shift_vy: reader.bool()?,
+            memory_increment: reader.bool()?,
+            jump_vx: reader.bool()?,
+            vf_reset: reader.bool()?,
+            wrap_sprites: reader.bool()?,
+            display_wait: reader.bool()?,
+        };
+
+        for key in new.keypad.iter_mut() {
+            *key = reader.bool()?;
+        }
+        new.key_releases = reader.u16()?;
+        new.waiting_for_key = reader.bool()?;
+        new.cycle_carry = reader.u32()? as usize;
+        new.vblank = reader.bool()?;
+        new.waiting_for_display = reader.bool()?;
+        new.rpl.copy_from_slice(reader.bytes(RPL_FLAGS)?);
+        new.exited = reader.bool()?;
+        new.rom_hash = reader.u64()?;
+        new.audio_pattern = if reader.bool()? {
+            Some(reader.bytes(AUDIO_PATTERN_SIZE)?.try_into().unwrap())
+        } else {
+            None
+        };
+        new.pitch = reader.u8()?;
+
+        if reader.pos != payload.len() {
+            return Err(invalid("unexpected data after the end"));
+        }
+        *self = new;
+        Ok(())
+    }
+}
+
+/// Reads little endian values out of a save state payload
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        let bytes = self.data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| Chip8Error::InvalidState("truncated".to_string()))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Chip8Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Chip8Error::InvalidState("bad flag".to_string())),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Chip8Error> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+/// CRC-32 (IEEE) of every byte value, built at compile time
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE), as used by zip and PNG
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffff_u32;
+    for byte in data {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running_emulator() -> Chip8 {
+        let mut emulator = crate::tests::example("snake");
+        for _ in 0..30 {
+            emulator.run_frame().unwrap();
+        }
+        emulator
+    }
+
+    fn error_message(result: Result<(), Chip8Error>) -> String {
+        match result {
+            Err(Chip8Error::InvalidState(why)) => why,
+            other => panic!("expected an invalid state, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut emulator = running_emulator();
+        let state = emulator.save_state();
+        for _ in 0..30 {
+            emulator.run_frame().unwrap();
+        }
+        let later = emulator.save_state();
+        assert_ne!(state, later);
+
+        emulator.load_state(&state).unwrap();
+        assert_eq!(emulator.save_state(), state);
+        // and runs on exactly as it did
+        for _ in 0..30 {
+            emulator.run_frame().unwrap();
+        }
+        assert_eq!(emulator.save_state(), later);
+
+        let mut fresh = Chip8::new(700);
+        fresh.load_state(&state).unwrap();
+        assert_eq!(fresh.save_state(), state);
+    }
+
+    #[test]
+    fn rejects_corrupted_states() {
+        let mut emulator = running_emulator();
+        let state = emulator.save_state();
+
+        let mut corrupted = state.clone();
+        corrupted[HEADER_SIZE + 0x300] ^= 0x01;
+        assert_eq!(error_message(emulator.load_state(&corrupted)), "checksum mismatch");
+
+        assert_eq!(error_message(emulator.load_state(&state[..state.len() - 1])), "truncated");
+        assert_eq!(error_message(emulator.load_state(b"PNG")), "not a save state");
+        let mut future = state.clone();
+        future[4] = 0xff;
+        assert!(error_message(emulator.load_state(&future)).starts_with("unsupported version"));
+
+        // nothing was touched along the way
+        assert_eq!(emulator.save_state(), state);
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+}
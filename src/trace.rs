@@ -0,0 +1,262 @@
+use std::io::{ self, Write };
+use std::ops::RangeInclusive;
+
+use crate::*;
+
+/// Last line of a trace that hit its size limit
+const STOPPED: &str = "trace stopped at the size limit\n";
+
+/// The instruction about to execute and the registers before it
+struct Before {
+    pc: u16,
+    bytes: Vec<u8>,
+    instruction: Option<Instruction>,
+    V: [u8; N_REGISTERS],
+    I: u16,
+    sp: usize,
+    delay_t: u8,
+    sound_t: u8,
+}
+
+/// Logs every executed instruction, one line each
+///
+/// Each line has the number of instructions executed so far, the address,
+/// the raw opcode, the disassembled instruction and the registers it changed:
+///
+/// ```text
+///        412  0x21E  6A05      LD VA, 0x05             VA=05
+///        413  0x220  FA55      LD [I], VA              I=030B
+/// ```
+///
+/// Only instructions within an address range are logged, and logging stops
+/// once the output would grow past a size limit.
+pub struct Trace<W: Write> {
+    out: W,
+    range: RangeInclusive<u16>,
+    /// Bytes the output may take up
+    limit: u64,
+    written: u64,
+    /// Instructions executed so far, logged or not
+    cycles: u64,
+    before: Option<Before>,
+    /// Set once the size limit is reached or writing fails
+    stopped: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Trace<W> {
+    pub fn new(out: W, range: RangeInclusive<u16>, limit: u64) -> Self {
+        Self {
+            out,
+            range,
+            limit,
+            written: 0,
+            cycles: 0,
+            before: None,
+            stopped: false,
+            error: None,
+        }
+    }
+
+    /// Instructions executed so far, including ones outside the range
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Whether the size limit was reached
+    pub fn truncated(&self) -> bool {
+        self.stopped && self.error.is_none()
+    }
+
+    /// Flushes the output, failing if any write along the way did
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(why) = self.error.take() {
+            return Err(why);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Logs the instruction `before` describes, followed by `outcome`
+    fn write_entry(&mut self, before: Before, outcome: &str) {
+        let opcode: String = before.bytes
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect();
+        let instruction = match before.instruction {
+            Some(instruction) => instruction.to_string(),
+            None => "???".to_string(),
+        };
+        let line = format!(
+            "{:>10}  {:#05X}  {opcode:<8}  {instruction:<22}  {outcome}",
+            self.cycles,
+            before.pc
+        );
+        self.write_line(&format!("{}\n", line.trim_end()));
+    }
+
+    fn write_line(&mut self, line: &str) {
+        // leaving room for the line saying the trace stopped
+        if self.written + (line.len() + STOPPED.len()) as u64 > self.limit {
+            self.stopped = true;
+            if self.written + STOPPED.len() as u64 <= self.limit {
+                if let Err(why) = self.out.write_all(STOPPED.as_bytes()) {
+                    self.error = Some(why);
+                }
+                self.written += STOPPED.len() as u64;
+            }
+            return;
+        }
+        match self.out.write_all(line.as_bytes()) {
+            Ok(()) => self.written += line.len() as u64,
+            Err(why) => {
+                self.stopped = true;
+                self.error = Some(why);
+            }
+        }
+    }
+}
+
+impl<W: Write> Observer for Trace<W> {
+    fn before_step(&mut self, emulator: &Chip8) {
+        self.cycles += 1;
+        let pc = emulator.pc();
+        if self.stopped || !self.range.contains(&pc) {
+            return;
+        }
+        let memory = &emulator.memory()[pc as usize..];
+        let instruction = Instruction::decode_at(memory);
+        let size = instruction.map_or(2, |instruction| instruction.size()).min(memory.len());
+        self.before = Some(Before {
+            pc,
+            bytes: memory[..size].to_vec(),
+            instruction,
+            V: *emulator.registers(),
+            I: emulator.index(),
+            sp: emulator.stack().len(),
+            delay_t: emulator.delay_timer(),
+            sound_t: emulator.sound_timer(),
+        });
+    }
+
+    fn after_step(&mut self, emulator: &Chip8, _result: &StepResult) {
+        let Some(before) = self.before.take() else {
+            return;
+        };
+        let mut changes = Vec::new();
+        for (x, (old, new)) in before.V.iter().zip(emulator.registers()).enumerate() {
+            if old != new {
+                changes.push(format!("V{x:X}={new:02X}"));
+            }
+        }
+        if before.I != emulator.index() {
+            changes.push(format!("I={:04X}", emulator.index()));
+        }
+        if before.sp != emulator.stack().len() {
+            changes.push(format!("SP={}", emulator.stack().len()));
+        }
+        if before.delay_t != emulator.delay_timer() {
+            changes.push(format!("DT={:02X}", emulator.delay_timer()));
+        }
+        if before.sound_t != emulator.sound_timer() {
+            changes.push(format!("ST={:02X}", emulator.sound_timer()));
+        }
+
+        self.write_entry(before, &changes.join(" "));
+    }
+
+    fn step_failed(&mut self, _emulator: &Chip8, error: &Chip8Error) {
+        // the faulting instruction is the most interesting line of all
+        if let Some(before) = self.before.take() {
+            self.write_entry(before, &format!("failed: {error}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::boot;
+
+    /// LD VA, 5, LD I, 0x30B, CALL 0x208, JP 0x206, then a subroutine:
+    /// LD DT, VA, LD ST, VA, RET
+    const ROM: [u8; 14] = [
+        0x6a, 0x05, 0xa3, 0x0b, 0x22, 0x08, 0x12, 0x06, 0xfa, 0x15, 0xfa, 0x18, 0x00, 0xee,
+    ];
+
+    /// Runs `cycles` instructions of the ROM, returning the trace
+    fn trace(cycles: usize, range: RangeInclusive<u16>, limit: u64) -> (String, bool) {
+        let mut emulator = boot(700, &ROM);
+        let mut trace = Trace::new(Vec::new(), range, limit);
+        emulator.run_cycles_observed(cycles, &mut trace).unwrap();
+        assert_eq!(trace.cycles(), cycles as u64);
+        let truncated = trace.truncated();
+        (String::from_utf8(trace.finish().unwrap()).unwrap(), truncated)
+    }
+
+    #[test]
+    fn lines_show_the_registers_changed() {
+        let (text, truncated) = trace(8, 0..=u16::MAX, u64::MAX);
+        assert!(!truncated);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "         1  0x200  6A05      LD VA, 0x05             VA=05",
+                "         2  0x202  A30B      LD I, 0x30B             I=030B",
+                "         3  0x204  2208      CALL 0x208              SP=1",
+                "         4  0x208  FA15      LD DT, VA               DT=05",
+                "         5  0x20A  FA18      LD ST, VA               ST=05",
+                "         6  0x20C  00EE      RET                     SP=0",
+                "         7  0x206  1206      JP 0x206",
+                "         8  0x206  1206      JP 0x206",
+            ]
+        );
+    }
+
+    #[test]
+    fn range_filters_by_address() {
+        let (text, _) = trace(8, 0x208..=0x20b, u64::MAX);
+        let lines: Vec<&str> = text.lines().collect();
+        // counting the instructions that weren't logged
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("         4  0x208"));
+        assert!(lines[1].starts_with("         5  0x20A"));
+    }
+
+    #[test]
+    fn limit_ends_with_the_stop_line() {
+        let (full, _) = trace(8, 0..=u16::MAX, u64::MAX);
+        for limit in [0, 10, STOPPED.len() as u64, 100, 200, full.len() as u64 - 1] {
+            let (text, truncated) = trace(8, 0..=u16::MAX, limit);
+            assert!(truncated, "{limit}");
+            assert!(text.len() as u64 <= limit, "{limit}: {text:?}");
+            if limit >= STOPPED.len() as u64 {
+                assert!(text.ends_with(STOPPED), "{limit}: {text:?}");
+                // cut at a line, not in the middle of one
+                assert!(full.starts_with(&text[..text.len() - STOPPED.len()]));
+            }
+        }
+        // room for the stop line is kept until the end
+        let (text, truncated) = trace(8, 0..=u16::MAX, (full.len() + STOPPED.len()) as u64);
+        assert!(!truncated);
+        assert_eq!(text, full);
+    }
+
+    #[test]
+    fn failures_are_logged() {
+        // LD V0, 1, then an invalid instruction
+        let mut emulator = boot(700, &[0x60, 0x01, 0xff, 0xff]);
+        let mut trace = Trace::new(Vec::new(), 0..=u16::MAX, u64::MAX);
+        assert!(emulator.run_cycles_observed(2, &mut trace).is_err());
+        let text = String::from_utf8(trace.finish().unwrap()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "         1  0x200  6001      LD V0, 0x01             V0=01",
+                "         2  0x202  FFFF      ???                     failed: Unknown instruction 0xFFFF at 0x202",
+            ]
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use std::fmt;
+
+use crate::*;
+
+/// How an instruction touched memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// FX65, 5XY3, DXYN sprite data and F002 audio patterns
+    Read,
+    /// FX33, FX55 and 5XY2
+    Write,
+    /// An instruction at the address was fetched
+    Execute,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// Memory read or written by an instruction, see [`Chip8::accesses`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub access: Access,
+    pub addr: u16,
+    /// Number of bytes from `addr` on
+    pub len: usize,
+}
+
+/// Watches a range of memory for reads, writes and/or execution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    /// First address watched
+    pub start: u16,
+    /// Last address watched, inclusive
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    /// Stop execution on a hit, see [`StepResult::watchpoint`], instead of
+    /// only recording it
+    pub pause: bool,
+}
+
+impl Watchpoint {
+    fn watches(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+/// An access to watched memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    /// Address of the instruction that made the access
+    pub pc: u16,
+    /// First watched address that was accessed
+    pub addr: u16,
+    pub access: Access,
+    /// Whether the watchpoint stops execution
+    pub pause: bool,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.access {
+            Access::Execute => write!(f, "execute at {:#05X}", self.addr),
+            _ => write!(f, "{} of {:#05X} by {:#05X}", self.access, self.addr, self.pc),
+        }
+    }
+}
+
+impl Chip8 {
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes the watchpoint at an index of [`Chip8::watchpoints`]
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Watchpoint hits since the last call, oldest first
+    pub fn take_watch_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.watch_hits)
+    }
+
+    /// Memory read and written by the last instruction executed
+    pub fn accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    /// Records a read or write of `len` bytes from `addr` on by the
+    /// instruction at `pc`, checking it against the watchpoints
+    pub(crate) fn access(&mut self, pc: u16, access: Access, addr: usize, len: usize) {
+        self.accesses.push(MemoryAccess { access, addr: addr as u16, len });
+        self.watch(pc, access, addr, len);
+    }
+
+    /// Records an access of `len` bytes from `addr` on by the instruction at
+    /// `pc`, if it touches any watched memory
+    pub(crate) fn watch(&mut self, pc: u16, access: Access, addr: usize, len: usize) {
+        if self.watchpoints.is_empty() || len == 0 {
+            return;
+        }
+        let last = addr + len - 1;
+        for watchpoint in self.watchpoints.iter() {
+            let (start, end) = (watchpoint.start as usize, watchpoint.end as usize);
+            if !watchpoint.watches(access) || last < start || addr > end {
+                continue;
+            }
+            self.watch_hits.push(WatchHit {
+                pc,
+                addr: addr.max(start) as u16,
+                access,
+                pause: watchpoint.pause,
+            });
+            self.watch_paused |= watchpoint.pause;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::boot;
+
+    fn watchpoint(start: u16, end: u16, access: &[Access], pause: bool) -> Watchpoint {
+        Watchpoint {
+            start,
+            end,
+            read: access.contains(&Access::Read),
+            write: access.contains(&Access::Write),
+            execute: access.contains(&Access::Execute),
+            pause,
+        }
+    }
+
+    /// LD I, 0x300, LD [I], V1, LD V2, [I], DRW V0, V0, 2, LD B, V0, JP 0x20A
+    const ROM: [u8; 12] = [0xa3, 0x00, 0xf1, 0x55, 0xf2, 0x65, 0xd0, 0x02, 0xf0, 0x33, 0x12, 0x0a];
+
+    #[test]
+    fn data_accesses_are_recorded() {
+        let mut emulator = boot(700, &ROM);
+        emulator.add_watchpoint(watchpoint(0x301, 0x301, &[Access::Read, Access::Write], false));
+        emulator.add_watchpoint(watchpoint(0x302, 0x3ff, &[Access::Read], false));
+        let result = emulator.run_cycles(5).unwrap();
+        assert!(!result.watchpoint);
+
+        let hit = |pc, addr, access| WatchHit { pc, addr, access, pause: false };
+        assert_eq!(
+            emulator.take_watch_hits(),
+            [
+                hit(0x202, 0x301, Access::Write),
+                hit(0x204, 0x301, Access::Read),
+                hit(0x204, 0x302, Access::Read),
+                hit(0x206, 0x301, Access::Read),
+                hit(0x208, 0x301, Access::Write),
+            ]
+        );
+        assert!(emulator.take_watch_hits().is_empty());
+        assert_eq!(hit(0x202, 0x301, Access::Write).to_string(), "write of 0x301 by 0x202");
+    }
+
+    #[test]
+    fn pausing_watchpoints_stop_after_the_access() {
+        let mut emulator = boot(700, &ROM);
+        emulator.add_watchpoint(watchpoint(0x300, 0x300, &[Access::Write], true));
+        assert!(emulator.run_cycles(100).unwrap().watchpoint);
+        assert_eq!(emulator.pc, 0x204);
+        assert!(emulator.run_cycles(100).unwrap().watchpoint);
+        assert_eq!(emulator.pc, 0x20a);
+        assert_eq!(emulator.take_watch_hits().len(), 2);
+
+        assert_eq!(emulator.remove_watchpoint(1), None);
+        assert!(emulator.remove_watchpoint(0).is_some());
+        assert!(!emulator.run_cycles(100).unwrap().watchpoint);
+    }
+
+    #[test]
+    fn execution_stops_after_the_instruction() {
+        let mut emulator = boot(700, &ROM);
+        emulator.add_watchpoint(watchpoint(0x204, 0x205, &[Access::Execute], true));
+        emulator.add_watchpoint(watchpoint(0x206, 0x206, &[Access::Read, Access::Write], true));
+        assert!(emulator.run_cycles(100).unwrap().watchpoint);
+        assert_eq!(emulator.pc, 0x206);
+        let hits = emulator.take_watch_hits();
+        assert_eq!(hits[0].to_string(), "execute at 0x204");
+        // and doesn't fire reading or writing the same address
+        assert!(!emulator.run_cycles(3).unwrap().watchpoint);
+    }
+
+    #[test]
+    fn execution_is_checked_on_fetch() {
+        // the very first instruction counts, with nothing arriving there
+        let mut emulator = boot(700, &ROM);
+        emulator.add_watchpoint(watchpoint(0x200, 0x200, &[Access::Execute], true));
+        assert!(emulator.step().unwrap().watchpoint);
+        assert_eq!(emulator.pc, 0x202);
+
+        // an instruction waiting in place (LD V0, K) only counts once
+        let mut emulator = boot(700, &[0xf0, 0x0a]);
+        emulator.add_watchpoint(watchpoint(0x200, 0x200, &[Access::Execute], false));
+        for _ in 0..3 {
+            assert!(emulator.step().unwrap().waiting_for_key);
+        }
+        assert_eq!(emulator.take_watch_hits().len(), 1);
+    }
+}